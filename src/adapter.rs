@@ -0,0 +1,150 @@
+use crate::error::{Error, Result};
+use pollster::FutureExt;
+
+/// Which GPU the window renders with.
+#[derive(Clone, PartialEq)]
+pub struct AdapterSettings {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    /// use the software adapter (e.g. llvmpipe) instead of a GPU
+    pub force_fallback_adapter: bool,
+    /// only use an adapter whose name contains this, ignoring case
+    pub name: Option<String>,
+}
+
+impl Default for AdapterSettings {
+    fn default() -> Self {
+        AdapterSettings {
+            backends: wgpu::Backends::PRIMARY,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            name: None,
+        }
+    }
+}
+
+/// Parse a comma separated list of backend names like `"vulkan, gl"`.
+pub fn parse_backends(names: &str) -> Option<wgpu::Backends> {
+    names
+        .split(',')
+        .map(|name| match name.trim().to_lowercase().as_str() {
+            "vulkan" | "vk" => Some(wgpu::Backends::VULKAN),
+            "metal" | "mtl" => Some(wgpu::Backends::METAL),
+            "dx12" | "d3d12" => Some(wgpu::Backends::DX12),
+            "gl" | "gles" | "opengl" => Some(wgpu::Backends::GL),
+            "primary" => Some(wgpu::Backends::PRIMARY),
+            "all" => Some(wgpu::Backends::all()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn parse_power_preference(name: &str) -> Option<wgpu::PowerPreference> {
+    match name.to_lowercase().as_str() {
+        "high" => Some(wgpu::PowerPreference::HighPerformance),
+        "low" => Some(wgpu::PowerPreference::LowPower),
+        "none" => Some(wgpu::PowerPreference::None),
+        _ => None,
+    }
+}
+
+pub fn create_instance(backends: wgpu::Backends) -> wgpu::Instance {
+    wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    })
+}
+
+/// All adapters of the given backends, whether or not they can present to a window.
+pub fn enumerate_adapters(backends: wgpu::Backends) -> Vec<wgpu::Adapter> {
+    create_instance(backends).enumerate_adapters(backends)
+}
+
+/// Pick the adapter that renders to `surface`, by name if one is set and otherwise by preference.
+pub fn request_adapter(
+    instance: &wgpu::Instance,
+    settings: &AdapterSettings,
+    surface: &wgpu::Surface,
+) -> Result<wgpu::Adapter> {
+    let Some(name) = &settings.name else {
+        return instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: settings.power_preference,
+                compatible_surface: Some(surface),
+                force_fallback_adapter: settings.force_fallback_adapter,
+            })
+            .block_on()
+            .ok_or(Error::NoAdapter);
+    };
+
+    let adapters: Vec<wgpu::Adapter> = instance
+        .enumerate_adapters(settings.backends)
+        .into_iter()
+        .filter(|adapter| adapter.is_surface_supported(surface))
+        .filter(|adapter| {
+            !settings.force_fallback_adapter
+                || adapter.get_info().device_type == wgpu::DeviceType::Cpu
+        })
+        .collect();
+    let names: Vec<String> = adapters
+        .iter()
+        .map(|adapter| adapter.get_info().name)
+        .collect();
+    match names
+        .iter()
+        .position(|candidate| candidate.to_lowercase().contains(&name.to_lowercase()))
+    {
+        Some(index) => Ok(adapters.into_iter().nth(index).unwrap()),
+        None => {
+            log::warn!(
+                "no adapter matching \"{}\", available: {}",
+                name,
+                names.join(", ")
+            );
+            Err(Error::NoAdapter)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_by_name() {
+        assert_eq!(parse_backends("vulkan"), Some(wgpu::Backends::VULKAN));
+        assert_eq!(
+            parse_backends(" Vulkan, gl "),
+            Some(wgpu::Backends::VULKAN | wgpu::Backends::GL)
+        );
+        assert_eq!(
+            parse_backends("mtl,d3d12"),
+            Some(wgpu::Backends::METAL | wgpu::Backends::DX12)
+        );
+        assert_eq!(parse_backends("all"), Some(wgpu::Backends::all()));
+        assert_eq!(parse_backends("primary"), Some(wgpu::Backends::PRIMARY));
+    }
+
+    #[test]
+    fn unknown_backend() {
+        assert_eq!(parse_backends("vulkan,webgpu"), None);
+        assert_eq!(parse_backends(""), None);
+    }
+
+    #[test]
+    fn power_preference_by_name() {
+        assert_eq!(
+            parse_power_preference("HIGH"),
+            Some(wgpu::PowerPreference::HighPerformance)
+        );
+        assert_eq!(
+            parse_power_preference("low"),
+            Some(wgpu::PowerPreference::LowPower)
+        );
+        assert_eq!(
+            parse_power_preference("none"),
+            Some(wgpu::PowerPreference::None)
+        );
+        assert_eq!(parse_power_preference("fast"), None);
+    }
+}
@@ -0,0 +1,294 @@
+use std::sync::Arc;
+
+use crate::construct;
+use crate::render;
+use crate::utils;
+use bytemuck::NoUninit;
+use wgpu::include_wgsl;
+
+/// Quads animated by one workgroup, `WORKGROUP_SIZE` in the shader.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// How the progress between the two keyframes of an animation is turned into the mix between them.
+#[repr(u32)]
+#[derive(Clone, Copy, NoUninit)]
+pub enum Easing {
+    Linear,
+    /// quadratic, starts slowly
+    EaseIn,
+    /// quadratic, ends slowly
+    EaseOut,
+    /// cubic smoothstep
+    EaseInOut,
+    /// half a cosine wave, a ping-pong with this easing moves like a sine
+    Sine,
+}
+
+/// What happens after the second keyframe of an animation.
+#[repr(u32)]
+#[derive(Clone, Copy, NoUninit)]
+pub enum Repeat {
+    /// stay at the second keyframe
+    Once,
+    /// jump back to the first keyframe
+    Loop,
+    /// move back to the first keyframe and start over
+    PingPong,
+}
+
+/// Motion of a quad, evaluated on the GPU from the time since the scene was set up. Quads only
+/// have to be uploaded again when their animations change.
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+pub struct Animation {
+    /// constant motion of the center in units per second
+    pub velocity: [f32; 2],
+    /// counter-clockwise rotation around the center in radians per second
+    pub angular_velocity: f32,
+    /// seconds from the first to the second keyframe, the keyframes are ignored if it is zero
+    pub duration: f32,
+    /// offset of the center at the first and second keyframe
+    pub start: [f32; 2],
+    pub end: [f32; 2],
+    /// time of the first keyframe for each axis in seconds, so that the axes can move out of
+    /// step, e.g. along a circle
+    pub delay: [f32; 2],
+    pub easing: Easing,
+    pub repeat: Repeat,
+}
+
+impl Animation {
+    /// Animation that leaves the quad where it is.
+    pub const NONE: Animation = Animation {
+        velocity: [0.0; 2],
+        angular_velocity: 0.0,
+        duration: 0.0,
+        start: [0.0; 2],
+        end: [0.0; 2],
+        delay: [0.0; 2],
+        easing: Easing::Linear,
+        repeat: Repeat::Once,
+    };
+
+    /// Move the center between two offsets, taking `duration` seconds from `start` to `end`.
+    pub fn keyframes(
+        start: [f32; 2],
+        end: [f32; 2],
+        duration: f32,
+        easing: Easing,
+        repeat: Repeat,
+    ) -> Animation {
+        Animation {
+            duration,
+            start,
+            end,
+            easing,
+            repeat,
+            ..Animation::NONE
+        }
+    }
+
+    /// Start the keyframes of each axis later, negative delays start them part of the way in.
+    pub fn delayed(self, x: f32, y: f32) -> Animation {
+        Animation {
+            delay: [x, y],
+            ..self
+        }
+    }
+
+    /// Rotate around the center with the given angular velocity in radians per second.
+    pub fn spinning(self, angular_velocity: f32) -> Animation {
+        Animation {
+            angular_velocity,
+            ..self
+        }
+    }
+
+    /// Express the motion in a parent coordinate system, like `Quad::transformed` does for the quad.
+    pub fn transformed(&self, transform: &utils::Transform) -> Animation {
+        let linear = |[x, y]: [f32; 2]| {
+            [
+                transform.a * x + transform.c * y,
+                transform.b * x + transform.d * y,
+            ]
+        };
+        Animation {
+            velocity: linear(self.velocity),
+            start: linear(self.start),
+            end: linear(self.end),
+            ..*self
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct ClockUniform {
+    time: f32,
+    num_quads: u32,
+    _padding: [u32; 2],
+}
+
+/// Compute pass that moves the quads of a layer according to their animations, writing the
+/// result into a separate buffer that is culled and drawn instead of the uploaded quads.
+pub struct AnimatePipeline {
+    device_arc: Arc<wgpu::Device>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl AnimatePipeline {
+    pub fn new(device_arc: Arc<wgpu::Device>) -> AnimatePipeline {
+        let shader = device_arc.create_shader_module(include_wgsl!("animate_shader.wgsl"));
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("animate bind group layout"),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, true),
+                    storage_entry(2, false),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let pipeline_layout = device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("animate pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device_arc.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("animate pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("animate"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        AnimatePipeline {
+            device_arc,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Set up animating new contents of a layer, reusing the buffers of `previous` if they are
+    /// large enough. Returns `None` if the layer has no animations.
+    pub fn prepare(
+        &self,
+        buffers: &construct::LayerBuffers,
+        quad_bind_group_layout: &wgpu::BindGroupLayout,
+        previous: Option<AnimatedQuads>,
+    ) -> Option<AnimatedQuads> {
+        let animations = buffers.animations.as_ref()?;
+        // the draw ranges cover all quads in order
+        let num_quads = buffers.draw_ranges.last().map_or(0, |range| range.end);
+        // the uploaded quads fit into a storage buffer, so the animated ones do as well
+        let num_bytes = num_quads as u64 * size_of::<render::Quad>() as u64;
+        let buffer_size = num_bytes
+            .next_power_of_two()
+            .min(construct::max_storage_size(&self.device_arc));
+        let (quads, clock_buffer) = match previous {
+            Some(previous) if previous.quads.buffer.size() >= num_bytes => {
+                (previous.quads, previous.clock_buffer)
+            }
+            _ => (
+                construct::create_storage_buffer(
+                    &self.device_arc,
+                    quad_bind_group_layout,
+                    buffer_size,
+                ),
+                self.device_arc.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("animate clock buffer"),
+                    size: size_of::<ClockUniform>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+            ),
+        };
+        let bind_group = self
+            .device_arc
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("animate bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffers.quads.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: animations.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: quads.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: clock_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+        Some(AnimatedQuads {
+            quads,
+            clock_buffer,
+            bind_group,
+            num_quads,
+        })
+    }
+
+    /// Move the quads of a layer to where they are `time` seconds after the scene was set up.
+    pub fn animate(
+        &self,
+        queue: &wgpu::Queue,
+        compute_pass: &mut wgpu::ComputePass,
+        animated: &AnimatedQuads,
+        time: f32,
+    ) {
+        let clock = ClockUniform {
+            time,
+            num_quads: animated.num_quads,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&animated.clock_buffer, 0, bytemuck::bytes_of(&clock));
+
+        // spread the workgroups over rows when they do not fit into one dimension
+        let num_workgroups = animated.num_quads.div_ceil(WORKGROUP_SIZE);
+        let max_workgroups = self
+            .device_arc
+            .limits()
+            .max_compute_workgroups_per_dimension;
+        let columns = num_workgroups.min(max_workgroups);
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &animated.bind_group, &[]);
+        compute_pass.dispatch_workgroups(columns, num_workgroups.div_ceil(columns.max(1)), 1);
+    }
+}
+
+/// Quads of a layer moved by their animations, updated every frame.
+pub struct AnimatedQuads {
+    pub quads: construct::StorageBuffer,
+    clock_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    num_quads: u32,
+}
@@ -0,0 +1,111 @@
+struct Quad {
+    center: vec2<f32>,
+    size: vec2<f32>,
+    color: vec4<f32>,
+    // column-major 2x2 matrix applied around the center
+    transform: vec4<f32>,
+    corner_radius: f32,
+    blur: f32,
+    flags: u32,
+    _padding: u32,
+};
+
+struct Animation {
+    velocity: vec2<f32>,
+    angular_velocity: f32,
+    // the keyframes are ignored if the duration is zero
+    duration: f32,
+    start: vec2<f32>,
+    end: vec2<f32>,
+    delay: vec2<f32>,
+    easing: u32,
+    repeat: u32,
+};
+
+struct Clock {
+    // seconds since the scene was set up
+    time: f32,
+    num_quads: u32,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+
+const EASING_LINEAR: u32 = 0u;
+const EASING_EASE_IN: u32 = 1u;
+const EASING_EASE_OUT: u32 = 2u;
+const EASING_EASE_IN_OUT: u32 = 3u;
+const EASING_SINE: u32 = 4u;
+
+const REPEAT_ONCE: u32 = 0u;
+const REPEAT_LOOP: u32 = 1u;
+const REPEAT_PING_PONG: u32 = 2u;
+
+@group(0) @binding(0) var<storage, read> quads: array<Quad>;
+@group(0) @binding(1) var<storage, read> animations: array<Animation>;
+@group(0) @binding(2) var<storage, read_write> animated_quads: array<Quad>;
+@group(0) @binding(3) var<uniform> clock: Clock;
+
+// position between the keyframes for the time since the first one, in units of the duration
+fn progress(t: vec2<f32>, repeat: u32) -> vec2<f32> {
+    switch repeat {
+        case REPEAT_LOOP: {
+            return t - floor(t);
+        }
+        case REPEAT_PING_PONG: {
+            return 1.0 - abs(1.0 - (t - 2.0 * floor(t * 0.5)));
+        }
+        default: {
+            return clamp(t, vec2<f32>(0.0), vec2<f32>(1.0));
+        }
+    }
+}
+
+fn ease(t: vec2<f32>, easing: u32) -> vec2<f32> {
+    switch easing {
+        case EASING_EASE_IN: {
+            return t * t;
+        }
+        case EASING_EASE_OUT: {
+            return t * (2.0 - t);
+        }
+        case EASING_EASE_IN_OUT: {
+            return t * t * (3.0 - 2.0 * t);
+        }
+        case EASING_SINE: {
+            return 0.5 - 0.5 * cos(t * 3.141592653589793);
+        }
+        default: {
+            return t;
+        }
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn animate(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    // large layers are dispatched as rows of workgroups
+    let index = (workgroup_id.y * num_workgroups.x + workgroup_id.x) * WORKGROUP_SIZE + local;
+    if index >= clock.num_quads {
+        return;
+    }
+    var quad = quads[index];
+    let animation = animations[index];
+
+    var offset = animation.velocity * clock.time;
+    if animation.duration > 0.0 {
+        let t = progress((clock.time - animation.delay) / animation.duration, animation.repeat);
+        offset += mix(animation.start, animation.end, ease(t, animation.easing));
+    }
+    quad.center += offset;
+
+    // spin around the center in the quad's own coordinate system
+    let angle = animation.angular_velocity * clock.time;
+    let rotation = mat2x2<f32>(cos(angle), sin(angle), -sin(angle), cos(angle));
+    let transform = mat2x2<f32>(quad.transform.xy, quad.transform.zw) * rotation;
+    quad.transform = vec4<f32>(transform[0], transform[1]);
+
+    animated_quads[index] = quad;
+}
@@ -0,0 +1,45 @@
+struct BlurParams {
+    // offset between two neighbouring taps in texture coordinates
+    direction: vec2<f32>,
+    sigma: f32,
+    radius: f32,
+};
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0) var source_texture: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> params: BlurParams;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> VertexOut {
+    // a single triangle covering the whole screen
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return VertexOut(
+        vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0),
+        uv
+    );
+}
+
+@fragment
+fn fs_blur(in: VertexOut) -> @location(0) vec4<f32> {
+    // one dimension of a separable gaussian kernel
+    var color = textureSampleLevel(source_texture, source_sampler, in.uv, 0.0);
+    var total = 1.0;
+    for (var i = 1; i <= i32(params.radius); i++) {
+        let weight = exp(-f32(i * i) / (2.0 * params.sigma * params.sigma));
+        let offset = params.direction * f32(i);
+        color += textureSampleLevel(source_texture, source_sampler, in.uv + offset, 0.0) * weight;
+        color += textureSampleLevel(source_texture, source_sampler, in.uv - offset, 0.0) * weight;
+        total += 2.0 * weight;
+    }
+    return color / total;
+}
+
+@fragment
+fn fs_blit(in: VertexOut) -> @location(0) vec4<f32> {
+    return textureSampleLevel(source_texture, source_sampler, in.uv, 0.0);
+}
@@ -1,12 +1,16 @@
 use std::{
-    sync::{atomic, mpsc, Arc},
+    collections::BTreeMap,
+    sync::{atomic, mpsc, Arc, Condvar, Mutex},
     thread,
 };
 
+use crate::error::{Error, Result};
 use crate::render;
+use crate::shader;
+use crate::timer;
+use crate::trace;
 use crate::ui;
 use crate::utils;
-use wgpu;
 
 #[derive(Clone)]
 struct StagingBuffer {
@@ -21,6 +25,15 @@ pub struct StorageBuffer {
     pub ready: Arc<atomic::AtomicBool>,
 }
 
+/// Smallest size class, requests are rounded up to a power of two of at least this many bytes.
+const MIN_SIZE_CLASS: u64 = 1024;
+/// Requests may be served by buffers up to 2^MAX_CLASS_SPAN times their size class.
+const MAX_CLASS_SPAN: u32 = 2;
+/// Frames over which the peak usage of a buffer pool is measured before it releases memory.
+const SHRINK_FRAMES: u32 = 300;
+/// How long a request waits for a buffer in use once the budget is exhausted.
+const BACKPRESSURE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(50);
+
 fn create_staging_buffer(device_arc: &Arc<wgpu::Device>, buffer_size: u64) -> StagingBuffer {
     StagingBuffer {
         buffer: device_arc.create_buffer(&wgpu::BufferDescriptor {
@@ -33,7 +46,15 @@ fn create_staging_buffer(device_arc: &Arc<wgpu::Device>, buffer_size: u64) -> St
     }
 }
 
-fn create_storage_buffer(
+/// Largest storage buffer the device can create and bind, storage buffers are always bound whole.
+pub fn max_storage_size(device_arc: &wgpu::Device) -> u64 {
+    let limits = device_arc.limits();
+    limits
+        .max_buffer_size
+        .min(limits.max_storage_buffer_binding_size as u64)
+}
+
+pub fn create_storage_buffer(
     device_arc: &Arc<wgpu::Device>,
     bind_group_layout: &wgpu::BindGroupLayout,
     buffer_size: u64,
@@ -43,12 +64,13 @@ fn create_storage_buffer(
         size: buffer_size,
         usage: wgpu::BufferUsages::COPY_DST
             | wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::VERTEX,
+            | wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::INDEX,
         mapped_at_creation: false,
     });
     let bind_group = device_arc.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("bind group"),
-        layout: &bind_group_layout,
+        layout: bind_group_layout,
         entries: &[wgpu::BindGroupEntry {
             binding: 0,
             resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
@@ -68,217 +90,1143 @@ fn create_storage_buffer(
 
 fn create_buffer_pool(descriptor: BufferPoolDescriptor) -> BufferPool {
     BufferPool {
+        max_staging_size: descriptor.device_arc.limits().max_buffer_size,
+        max_storage_size: max_storage_size(&descriptor.device_arc),
         device_arc: descriptor.device_arc,
         bind_group_layout: descriptor.bind_group_layout,
-        staging_buffers: Vec::new(),
-        storage_buffers: Vec::new(),
-        buffer_size: descriptor.initial_buffer_size,
+        staging: Buckets::default(),
+        storage: Buckets::default(),
+        budget: descriptor.budget,
+        trace: descriptor.trace,
+        stats: PoolStats {
+            budget: descriptor.budget,
+            ..Default::default()
+        },
+        frames: 0,
     }
 }
 
 struct BufferPoolDescriptor {
     device_arc: Arc<wgpu::Device>,
     bind_group_layout: wgpu::BindGroupLayout,
-    initial_buffer_size: u64,
+    /// bytes the pooled buffers may take up, `None` for no limit
+    budget: Option<u64>,
+    trace: trace::Tracer,
+}
+
+/// Counters of a buffer pool, reported through the window statistics.
+#[derive(Clone, Copy, Default)]
+pub struct PoolStats {
+    /// buffers created because none was ready
+    pub allocations: u64,
+    /// requests served with a buffer that was ready
+    pub reuses: u64,
+    /// idle buffers dropped to stay within the budget or after low usage
+    pub releases: u64,
+    /// size classes dropped entirely after going unused
+    pub shrinks: u64,
+    /// requests that waited for a buffer in use because the budget was exhausted
+    pub waits: u64,
+    /// buffers created beyond the budget because waiting did not free one in time
+    pub over_budget: u64,
+    pub allocated_bytes: u64,
+    /// bytes of buffers in use by the GPU or the render thread
+    pub in_flight_bytes: u64,
+    /// staging and storage buffers held by the pool
+    pub buffers: u64,
+    pub budget: Option<u64>,
+}
+
+impl PoolStats {
+    /// Counters of several pools added up, the budget is unlimited if any pool's is.
+    pub fn total(pools: &[PoolStats]) -> PoolStats {
+        PoolStats {
+            allocations: pools.iter().map(|pool| pool.allocations).sum(),
+            reuses: pools.iter().map(|pool| pool.reuses).sum(),
+            releases: pools.iter().map(|pool| pool.releases).sum(),
+            shrinks: pools.iter().map(|pool| pool.shrinks).sum(),
+            waits: pools.iter().map(|pool| pool.waits).sum(),
+            over_budget: pools.iter().map(|pool| pool.over_budget).sum(),
+            allocated_bytes: pools.iter().map(|pool| pool.allocated_bytes).sum(),
+            in_flight_bytes: pools.iter().map(|pool| pool.in_flight_bytes).sum(),
+            buffers: pools.iter().map(|pool| pool.buffers).sum(),
+            budget: match pools.is_empty() {
+                true => None,
+                false => pools.iter().map(|pool| pool.budget).sum(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} MB allocated ({} allocations, {} reuses, {} releases, {} shrinks, {} waits, {} over budget)",
+            self.allocated_bytes as f64 / (1024.0 * 1024.0),
+            self.allocations,
+            self.reuses,
+            self.releases,
+            self.shrinks,
+            self.waits,
+            self.over_budget
+        )
+    }
+}
+
+/// Buffer handed out by the pool, ready again once the GPU and the render thread are done with it.
+trait PoolBuffer: Clone {
+    fn ready(&self) -> &atomic::AtomicBool;
+
+    fn is_ready(&self) -> bool {
+        self.ready().load(atomic::Ordering::SeqCst)
+    }
+}
+
+impl PoolBuffer for StagingBuffer {
+    fn ready(&self) -> &atomic::AtomicBool {
+        &self.ready
+    }
+}
+
+impl PoolBuffer for StorageBuffer {
+    fn ready(&self) -> &atomic::AtomicBool {
+        &self.ready
+    }
+}
+
+/// Size class of a request, the power of two its buffer is created with, unless that is more
+/// than the largest buffer the device supports.
+fn size_class(min_size: u64, max_size: u64) -> u64 {
+    min_size
+        .next_power_of_two()
+        .max(MIN_SIZE_CLASS)
+        .min(max_size)
+}
+
+/// Buffers of one size class.
+struct Bucket<B> {
+    buffers: Vec<B>,
+    /// most buffers in use at the end of a frame since the last trim
+    peak_in_use: usize,
+}
+
+/// Buffers of one kind by size class, so that buffers of different sizes coexist.
+struct Buckets<B>(BTreeMap<u64, Bucket<B>>);
+
+impl<B> Default for Buckets<B> {
+    fn default() -> Self {
+        Buckets(BTreeMap::new())
+    }
+}
+
+impl<B: PoolBuffer> Buckets<B> {
+    /// Classes that may serve a request of size class `class`, larger buffers are only used up to
+    /// a limit so that small layers do not hold on to the buffers of large ones.
+    fn fitting(&self, class: u64) -> impl Iterator<Item = &Bucket<B>> {
+        self.0
+            .range(class..=class << MAX_CLASS_SPAN)
+            .map(|(_, bucket)| bucket)
+    }
+
+    /// Take the smallest fitting ready buffer, marking it as in use.
+    fn take_ready(&self, class: u64) -> Option<B> {
+        self.fitting(class).find_map(|bucket| {
+            let buffer = bucket.buffers.iter().find(|buffer| buffer.is_ready())?;
+            buffer.ready().store(false, atomic::Ordering::SeqCst);
+            Some(buffer.clone())
+        })
+    }
+
+    fn any_in_use(&self, class: u64) -> bool {
+        self.fitting(class)
+            .any(|bucket| bucket.buffers.iter().any(|buffer| !buffer.is_ready()))
+    }
+
+    fn push(&mut self, class: u64, buffer: B) {
+        self.0
+            .entry(class)
+            .or_insert_with(|| Bucket {
+                buffers: Vec::new(),
+                peak_in_use: 0,
+            })
+            .buffers
+            .push(buffer);
+    }
+
+    fn allocated_bytes(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|(class, bucket)| class * bucket.buffers.len() as u64)
+            .sum()
+    }
+
+    fn len(&self) -> usize {
+        self.0.values().map(|bucket| bucket.buffers.len()).sum()
+    }
+
+    /// Bytes in use, recording the number of buffers in use per class.
+    fn record_usage(&mut self) -> u64 {
+        let mut in_use_bytes = 0;
+        for (class, bucket) in self.0.iter_mut() {
+            let in_use = bucket.buffers.iter().filter(|b| !b.is_ready()).count();
+            bucket.peak_in_use = bucket.peak_in_use.max(in_use);
+            in_use_bytes += class * in_use as u64;
+        }
+        in_use_bytes
+    }
+
+    /// Drop ready buffers, largest first, until at least `bytes` have been released.
+    /// Returns the number of buffers and bytes released.
+    fn release(&mut self, bytes: u64) -> (usize, u64) {
+        let (mut count, mut released) = (0, 0);
+        for (class, bucket) in self.0.iter_mut().rev() {
+            bucket.buffers.retain(|buffer| {
+                if released >= bytes || !buffer.is_ready() {
+                    return true;
+                }
+                count += 1;
+                released += class;
+                false
+            });
+        }
+        self.0.retain(|_, bucket| !bucket.buffers.is_empty());
+        (count, released)
+    }
+
+    /// Drop idle buffers beyond the peak number in use of each class, keeping a spare one in
+    /// classes that were used. Returns the number of buffers and classes dropped.
+    fn trim(&mut self) -> (usize, usize) {
+        let mut count = 0;
+        for bucket in self.0.values_mut() {
+            let keep = match bucket.peak_in_use {
+                0 => 0,
+                peak => peak + 1,
+            };
+            let mut excess = bucket.buffers.len().saturating_sub(keep);
+            bucket.buffers.retain(|buffer| {
+                if excess == 0 || !buffer.is_ready() {
+                    return true;
+                }
+                excess -= 1;
+                count += 1;
+                false
+            });
+            bucket.peak_in_use = 0;
+        }
+        let classes = self.0.len();
+        self.0.retain(|_, bucket| !bucket.buffers.is_empty());
+        (count, classes - self.0.len())
+    }
+}
+
+/// Bytes beyond `budget` once a buffer of `size` bytes is added to those already allocated.
+fn excess_bytes(budget: Option<u64>, allocated: u64, size: u64) -> u64 {
+    budget.map_or(0, |budget| (allocated + size).saturating_sub(budget))
 }
 
 struct BufferPool {
     device_arc: Arc<wgpu::Device>,
     bind_group_layout: wgpu::BindGroupLayout,
-    staging_buffers: Vec<StagingBuffer>,
-    storage_buffers: Vec<StorageBuffer>,
-    buffer_size: u64,
+    staging: Buckets<StagingBuffer>,
+    storage: Buckets<StorageBuffer>,
+    /// largest buffers the device supports, uploads are split over staging buffers of at most
+    /// this size
+    max_staging_size: u64,
+    max_storage_size: u64,
+    budget: Option<u64>,
+    trace: trace::Tracer,
+    stats: PoolStats,
+    /// frames since the pool was last trimmed
+    frames: u32,
 }
 
 impl BufferPool {
     pub fn request_staging(&mut self, min_size: u64) -> StagingBuffer {
-        self.check_size(min_size);
-
-        // check if there are any available buffers
-        let result = match self
-            .staging_buffers
-            .iter()
-            .filter(|b| b.ready.load(atomic::Ordering::SeqCst))
-            .next()
-        {
-            Some(result) => result.clone(),
-            None => {
-                // no mapped staging buffer available, create a new one
-                let new_buffer = create_staging_buffer(&self.device_arc, self.buffer_size);
-                self.staging_buffers.push(new_buffer.clone());
-                new_buffer
-            }
-        };
-
-        result.ready.store(false, atomic::Ordering::SeqCst);
-        result
+        let _span = self.trace.span("request staging").arg("bytes", min_size);
+        self.request(
+            size_class(min_size, self.max_staging_size),
+            |pool| &mut pool.staging,
+            |pool, size| create_staging_buffer(&pool.device_arc, size),
+        )
     }
 
     pub fn request_storage(&mut self, min_size: u64) -> StorageBuffer {
-        self.check_size(min_size);
+        let _span = self.trace.span("request storage").arg("bytes", min_size);
+        self.request(
+            size_class(min_size, self.max_storage_size),
+            |pool| &mut pool.storage,
+            |pool, size| create_storage_buffer(&pool.device_arc, &pool.bind_group_layout, size),
+        )
+    }
 
-        // check if there are any available buffers
-        let result = match self
-            .storage_buffers
-            .iter()
-            .filter(|b| b.ready.load(atomic::Ordering::SeqCst))
-            .next()
-        {
-            Some(result) => result.clone(),
-            None => {
-                // no storage buffers available, create a new one
-                let new_buffer = create_storage_buffer(
-                    &self.device_arc,
-                    &self.bind_group_layout,
-                    self.buffer_size,
+    /// Reuse a ready buffer, or create one if that stays within the budget. Once the budget is
+    /// exhausted, wait for a buffer in use to become ready before going beyond it.
+    fn request<B: PoolBuffer>(
+        &mut self,
+        class: u64,
+        buckets: fn(&mut BufferPool) -> &mut Buckets<B>,
+        create: fn(&BufferPool, u64) -> B,
+    ) -> B {
+        if let Some(buffer) = buckets(self).take_ready(class) {
+            self.stats.reuses += 1;
+            return buffer;
+        }
+
+        // make room by dropping idle buffers that cannot serve this request
+        let excess = self.excess_bytes(class);
+        if excess > 0 {
+            let (staging_count, released) = self.staging.release(excess);
+            let (storage_count, _) = self.storage.release(excess.saturating_sub(released));
+            self.stats.releases += (staging_count + storage_count) as u64;
+        }
+
+        // back-pressure: buffers in use become ready once the GPU and the render thread are done
+        if self.excess_bytes(class) > 0 && buckets(self).any_in_use(class) {
+            let _span = self.trace.span("pool wait");
+            self.stats.waits += 1;
+            let deadline = std::time::Instant::now() + BACKPRESSURE_TIMEOUT;
+            while std::time::Instant::now() < deadline {
+                self.device_arc.poll(wgpu::Maintain::Poll);
+                if let Some(buffer) = buckets(self).take_ready(class) {
+                    self.stats.reuses += 1;
+                    return buffer;
+                }
+                thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+
+        if self.excess_bytes(class) > 0 {
+            if self.stats.over_budget == 0 {
+                log::warn!(
+                    "buffer pool budget of {:.2} MB exceeded, the scene needs more memory",
+                    self.budget.unwrap_or(0) as f64 / (1024.0 * 1024.0)
                 );
-                self.storage_buffers.push(new_buffer.clone());
-                new_buffer
             }
-        };
+            self.stats.over_budget += 1;
+        }
+        let buffer = create(self, class);
+        buffer.ready().store(false, atomic::Ordering::SeqCst);
+        buckets(self).push(class, buffer.clone());
+        self.stats.allocations += 1;
+        buffer
+    }
 
-        result.ready.store(false, atomic::Ordering::SeqCst);
-        result
+    /// Bytes to release before a buffer of size class `class` fits into the budget.
+    fn excess_bytes(&self, class: u64) -> u64 {
+        excess_bytes(self.budget, self.allocated_bytes(), class)
     }
 
-    fn check_size(&mut self, min_size: u64) {
-        while self.buffer_size < min_size {
-            // grow the buffer size and discard all available buffers
-            self.buffer_size *= 2;
-            self.staging_buffers.clear();
-            self.storage_buffers.clear();
+    /// Memory held by the pooled staging and storage buffers.
+    fn allocated_bytes(&self) -> u64 {
+        self.staging.allocated_bytes() + self.storage.allocated_bytes()
+    }
+
+    /// Record the usage of a constructed frame and release buffers that went unused for a
+    /// while. Returns the current counters.
+    fn end_frame(&mut self) -> PoolStats {
+        let in_flight_bytes = self.staging.record_usage() + self.storage.record_usage();
+        self.frames += 1;
+        if self.frames == SHRINK_FRAMES {
+            self.frames = 0;
+            let (staging_count, staging_classes) = self.staging.trim();
+            let (storage_count, storage_classes) = self.storage.trim();
+            self.stats.releases += (staging_count + storage_count) as u64;
+            self.stats.shrinks += (staging_classes + storage_classes) as u64;
         }
+
+        self.stats.allocated_bytes = self.allocated_bytes();
+        self.stats.in_flight_bytes = in_flight_bytes;
+        self.stats.buffers = (self.staging.len() + self.storage.len()) as u64;
+        self.stats
     }
 }
 
+pub struct MeshBuffers {
+    pub vertices: StorageBuffer,
+    pub indices: StorageBuffer,
+    pub draw_ranges: Vec<render::DrawRange>,
+}
+
+/// GPU copy of the contents of a layer.
+pub struct LayerBuffers {
+    pub quads: StorageBuffer,
+    /// one animation per quad, `None` if the layer has no animated quads
+    pub animations: Option<StorageBuffer>,
+    pub draw_ranges: Vec<render::DrawRange>,
+    pub backdrop_blur: bool,
+    pub mesh: Option<MeshBuffers>,
+}
+
+impl LayerBuffers {
+    pub fn into_storage_buffers(self) -> impl Iterator<Item = StorageBuffer> {
+        std::iter::once(self.quads).chain(self.animations).chain(
+            self.mesh
+                .into_iter()
+                .flat_map(|mesh| [mesh.vertices, mesh.indices]),
+        )
+    }
+}
+
+pub struct LayerUpdate {
+    pub blend: render::BlendMode,
+    pub camera: Option<utils::Transform>,
+    pub shader: shader::LayerShader,
+    /// new contents of the layer, `None` if the layer did not change since the last message
+    pub buffers: Option<LayerBuffers>,
+}
+
 pub struct ConstructionWorkerMessage {
-    pub storage_buffer: StorageBuffer,
-    pub num_instances: u32,
+    /// connects the worker and render thread spans of a frame in a trace
+    pub flow_id: u64,
+    /// earliest input reflected by the frame, for measuring the input-to-photon latency
+    pub input_time: Option<std::time::Instant>,
+    pub layers: Vec<LayerUpdate>,
+    pub stats: WorkerStats,
+}
+
+/// Numbers of the construction worker shown by the statistics overlay.
+#[derive(Clone, Copy, Default)]
+pub struct WorkerStats {
+    /// seconds spent constructing the frame of the message
+    pub construct_time: f64,
+    /// quads in all layers
+    pub instances: usize,
+    pub pool_bytes: u64,
+}
+
+/// Region of a staging buffer to be copied to a storage buffer.
+struct StagingCopy<'a> {
+    /// index of the staging buffer among those of the upload
+    staging: usize,
+    offset: u64,
+    storage: &'a StorageBuffer,
+    storage_offset: u64,
+    num_bytes: u64,
 }
 
+/// First error raised in a wgpu callback, picked up by the worker loop.
+type CallbackError = Arc<Mutex<Option<Error>>>;
+
 fn staging_to_storage(
-    staging: StagingBuffer,
-    storage: &StorageBuffer,
+    stagings: Vec<StagingBuffer>,
+    copies: &[StagingCopy],
     device_arc: &Arc<wgpu::Device>,
     queue_arc: &Arc<wgpu::Queue>,
-    num_bytes: u64,
+    callback_error: &CallbackError,
+    mut gpu_timer: Option<&mut timer::GpuTimer>,
+    trace: &trace::Tracer,
 ) {
-    // copy staging buffer to storage buffer
+    let _span = trace.span("staging copy").arg("copies", copies.len());
+    // copy staging buffer regions to storage buffers
     let mut encoder = device_arc.create_command_encoder(&wgpu::CommandEncoderDescriptor {
         label: Some("staging-to-storage encoder"),
     });
-    encoder.copy_buffer_to_buffer(&staging.buffer, 0, &storage.buffer, 0, num_bytes);
+    if let Some(gpu_timer) = &gpu_timer {
+        gpu_timer.write_begin(&mut encoder);
+    }
+    for copy in copies {
+        encoder.copy_buffer_to_buffer(
+            &stagings[copy.staging].buffer,
+            copy.offset,
+            &copy.storage.buffer,
+            copy.storage_offset,
+            copy.num_bytes,
+        );
+    }
+    if let Some(gpu_timer) = &mut gpu_timer {
+        gpu_timer.write_end(&mut encoder);
+        gpu_timer.resolve(&mut encoder);
+    }
 
     // submit the copy command
     queue_arc.submit(std::iter::once(encoder.finish()));
+    if let Some(gpu_timer) = gpu_timer {
+        gpu_timer.map();
+    }
 
-    // re-map the staging buffer after the copy is done and mark it as ready once it has been mapped
+    // re-map the staging buffers after the copy is done and mark them as ready once they have
+    // been mapped
     let device_arc = device_arc.clone();
+    let callback_error = callback_error.clone();
     queue_arc.on_submitted_work_done(move || {
-        staging
-            .buffer
-            .slice(..)
-            .map_async(wgpu::MapMode::Write, move |result| match result {
-                Ok(_) => {
-                    // mark the buffer as ready
-                    staging.ready.store(true, atomic::Ordering::SeqCst);
-                }
-                Err(e) => {
-                    eprintln!("failed to re-map staging buffer: {:?}", e);
-                }
-            });
+        for staging in stagings {
+            let callback_error = callback_error.clone();
+            let ready = staging.ready.clone();
+            staging
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Write, move |result| match result {
+                    Ok(_) => {
+                        // mark the buffer as ready
+                        ready.store(true, atomic::Ordering::SeqCst);
+                    }
+                    Err(e) => {
+                        callback_error
+                            .lock()
+                            .unwrap()
+                            .get_or_insert(Error::BufferMap(e));
+                    }
+                });
+        }
         // poll the device to avoid BufferAsyncError
         device_arc.poll(wgpu::Maintain::Poll);
     });
 }
 
-pub fn create_construction_worker(descriptor: ConstructionWorkerDescriptor) -> ConstructionWorker {
-    println!("├─ starting construction worker");
+/// Part of a region placed in a staging buffer.
+#[derive(Debug, PartialEq)]
+struct StagedPart {
+    region: usize,
+    region_offset: u64,
+    /// index of the staging buffer among those of the plan
+    staging: usize,
+    offset: u64,
+    num_bytes: u64,
+}
+
+/// Lay out regions of the given sizes one after the other in staging buffers of at most
+/// `max_staging_size` bytes, splitting regions that do not fit into the rest of a buffer.
+/// Returns the sizes of the staging buffers and the parts of the regions in order.
+fn plan_staging(
+    sizes: impl IntoIterator<Item = u64>,
+    max_staging_size: u64,
+) -> (Vec<u64>, Vec<StagedPart>) {
+    let mut staging_sizes: Vec<u64> = Vec::new();
+    let mut parts = Vec::new();
+    for (region, size) in sizes.into_iter().enumerate() {
+        let mut region_offset = 0;
+        while region_offset < size {
+            match staging_sizes.last() {
+                Some(&staging_size) if staging_size < max_staging_size => {}
+                _ => staging_sizes.push(0),
+            }
+            let staging = staging_sizes.len() - 1;
+            let offset = staging_sizes[staging];
+            let num_bytes = (size - region_offset).min(max_staging_size - offset);
+            parts.push(StagedPart {
+                region,
+                region_offset,
+                staging,
+                offset,
+                num_bytes,
+            });
+            staging_sizes[staging] += num_bytes;
+            region_offset += num_bytes;
+        }
+    }
+    (staging_sizes, parts)
+}
+
+/// Write regions into staging buffers one after the other, splitting regions that do not fit
+/// into the rest of a staging buffer.
+fn stage_regions<'a>(
+    regions: Vec<(&[u8], &'a StorageBuffer)>,
+    buffer_pool: &mut BufferPool,
+    stagings: &mut Vec<StagingBuffer>,
+    copies: &mut Vec<StagingCopy<'a>>,
+) {
+    let (staging_sizes, parts) = plan_staging(
+        regions.iter().map(|(data, _)| data.len() as u64),
+        buffer_pool.max_staging_size,
+    );
+    // every staging buffer holds at least one part
+    let parts = parts.chunk_by(|a, b| a.staging == b.staging);
+    for (staging_size, parts) in staging_sizes.into_iter().zip(parts) {
+        let staging_buffer = buffer_pool.request_staging(staging_size);
+        let mut view = staging_buffer
+            .buffer
+            .slice(0..staging_size)
+            .get_mapped_range_mut();
+        for part in parts {
+            let (data, storage) = regions[part.region];
+            let (offset, region_offset) = (part.offset as usize, part.region_offset as usize);
+            let len = part.num_bytes as usize;
+            view[offset..offset + len].copy_from_slice(&data[region_offset..region_offset + len]);
+            copies.push(StagingCopy {
+                staging: stagings.len(),
+                offset: part.offset,
+                storage,
+                storage_offset: part.region_offset,
+                num_bytes: part.num_bytes,
+            });
+        }
+        drop(view);
+        staging_buffer.buffer.unmap();
+        stagings.push(staging_buffer);
+    }
+}
+
+/// Copy the contents of the layers with a version to upload into freshly requested storage
+/// buffers, going through staging buffers no larger than the device supports.
+fn upload_layers(
+    layers: &[ui::Layer],
+    versions: &[Option<u64>],
+    buffer_pool: &mut BufferPool,
+    device_arc: &Arc<wgpu::Device>,
+    queue_arc: &Arc<wgpu::Queue>,
+    callback_error: &CallbackError,
+    gpu_timer: Option<&mut timer::GpuTimer>,
+) -> Result<Vec<Option<LayerBuffers>>> {
+    // check the contents against the device limits before requesting any buffer
+    for (layer, _) in layers.iter().zip(versions).filter(|(_, v)| v.is_some()) {
+        let mesh = layer.mesh();
+        let bytes = [
+            size_of_val(layer.quads()),
+            size_of_val(layer.animations()),
+            size_of_val(mesh.vertices.as_slice()),
+            size_of_val(mesh.indices.as_slice()),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0) as u64;
+        if bytes > buffer_pool.max_storage_size {
+            return Err(Error::BufferSize {
+                layer: layer.name.clone(),
+                bytes,
+                limit: buffer_pool.max_storage_size,
+            });
+        }
+    }
+
+    // request storage buffers for the changed layers
+    let mut num_bytes = 0;
+    let buffers: Vec<Option<LayerBuffers>> = layers
+        .iter()
+        .zip(versions)
+        .map(|(layer, version)| {
+            if version.is_none() {
+                return None;
+            }
+            let mesh = layer.mesh();
+            let quad_bytes = size_of_val(layer.quads()) as u64;
+            let animation_bytes = size_of_val(layer.animations()) as u64;
+            let vertex_bytes = size_of_val(mesh.vertices.as_slice()) as u64;
+            let index_bytes = size_of_val(mesh.indices.as_slice()) as u64;
+            num_bytes += quad_bytes + animation_bytes + vertex_bytes + index_bytes;
+            Some(LayerBuffers {
+                quads: buffer_pool.request_storage(quad_bytes),
+                animations: match animation_bytes {
+                    0 => None,
+                    _ => Some(buffer_pool.request_storage(animation_bytes)),
+                },
+                draw_ranges: layer.quad_draw_ranges().to_vec(),
+                backdrop_blur: layer.has_backdrop_blur(),
+                mesh: match index_bytes {
+                    0 => None,
+                    _ => Some(MeshBuffers {
+                        vertices: buffer_pool.request_storage(vertex_bytes),
+                        indices: buffer_pool.request_storage(index_bytes),
+                        draw_ranges: layer.mesh_draw_ranges().to_vec(),
+                    }),
+                },
+            })
+        })
+        .collect();
+    if num_bytes == 0 {
+        return Ok(buffers);
+    }
+
+    // the quads and tessellated paths of each layer, the animations go through staging buffers
+    // of their own, so that both stay within the device limits for large animated layers
+    let mut regions = Vec::new();
+    let mut animation_regions = Vec::new();
+    for (layer, buffers) in layers.iter().zip(buffers.iter()) {
+        let Some(buffers) = buffers else {
+            continue;
+        };
+        let mesh = layer.mesh();
+        regions.push((bytemuck::cast_slice(layer.quads()), &buffers.quads));
+        if let Some(animations) = &buffers.animations {
+            animation_regions.push((bytemuck::cast_slice(layer.animations()), animations));
+        }
+        if let Some(mesh_buffers) = &buffers.mesh {
+            regions.push((bytemuck::cast_slice(&mesh.vertices), &mesh_buffers.vertices));
+            regions.push((bytemuck::cast_slice(&mesh.indices), &mesh_buffers.indices));
+        }
+    }
+    let mut stagings = Vec::new();
+    let mut copies = Vec::new();
+    stage_regions(regions, buffer_pool, &mut stagings, &mut copies);
+    stage_regions(animation_regions, buffer_pool, &mut stagings, &mut copies);
+
+    // copy the staging buffers to the storage buffers
+    staging_to_storage(
+        stagings,
+        &copies,
+        device_arc,
+        queue_arc,
+        callback_error,
+        gpu_timer,
+        &buffer_pool.trace,
+    );
+    Ok(buffers)
+}
+
+pub fn create_construction_worker(
+    descriptor: ConstructionWorkerDescriptor,
+) -> Result<ConstructionWorker> {
+    log::debug!("starting construction worker");
 
-    let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+    let mailbox = Arc::new(Mailbox {
+        changes: descriptor.changes.clone(),
+        ..Default::default()
+    });
     let alive = Arc::new(atomic::AtomicBool::new(true));
+    let (scene_sender, scene_receiver) = mpsc::channel();
 
-    ConstructionWorker {
-        receiver,
-        alive: alive.clone(),
-        worker_handle: thread::Builder::new()
-            .name("construction worker".to_string())
-            .spawn(move || {
-                // create quad manager and setup example quads
-                // Note: this will happen outside of the library
-                let mut ui_state = ui::UIState::new();
-                ui_state.setup();
+    let worker_handle = thread::Builder::new()
+        .name(format!("construction worker ({})", descriptor.name))
+        .spawn({
+            let alive = alive.clone();
+            let mailbox = mailbox.clone();
+            move || -> (Option<WorkerScene>, Result<()>) {
+                // the worker is dropped without a scene if the renderer fails to start
+                let Ok(WorkerScene {
+                    mut ui_state,
+                    start_time: worker_start,
+                }) = scene_receiver.recv()
+                else {
+                    return (None, Ok(()));
+                };
+
+                // dropped last, also when the worker panics
+                let _close_on_exit = CloseOnExit {
+                    mailbox: mailbox.clone(),
+                    wake: descriptor.wake.clone(),
+                };
 
                 // create buffer pool
                 let mut buffer_pool = create_buffer_pool(BufferPoolDescriptor {
                     device_arc: descriptor.device_arc.clone(),
                     bind_group_layout: descriptor.bind_group_layout,
-                    initial_buffer_size: 1024,
+                    budget: descriptor.pool_budget,
+                    trace: descriptor.trace.clone(),
                 });
 
-                let worker_start = std::time::Instant::now();
-                let mut stats = utils::Stats::default();
+                // version of each layer the render thread currently holds
+                let mut uploaded_versions: Vec<Option<u64>> = Vec::new();
+                // blend mode, camera and shader of each layer as last sent
+                let mut sent_settings: Vec<LayerSettings> = Vec::new();
+
+                // copies are timed from inside the encoder, outside of any pass
+                let mut gpu_timer = descriptor
+                    .device_arc
+                    .features()
+                    .contains(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS)
+                    .then(|| {
+                        timer::GpuTimer::new(
+                            descriptor.device_arc.clone(),
+                            &descriptor.queue_arc,
+                            "staging-to-storage timestamps",
+                        )
+                    })
+                    .flatten();
+
+                let callback_error = CallbackError::default();
+                let mut result = Ok(());
+                let mut frame = 0;
+
                 while alive.load(atomic::Ordering::SeqCst) {
+                    if let Some(e) = callback_error.lock().unwrap().take() {
+                        result = Err(e);
+                        break;
+                    }
+
                     // start measuring time
                     let loop_start = std::time::Instant::now();
+                    // changes noted from here on wake the worker up if it finds nothing to send
+                    let seen_changes = descriptor.changes.count();
+                    // input that arrived since the last frame is reflected by this one
+                    let input_time = descriptor.pending_input.lock().unwrap().take();
+                    frame += 1;
+                    let _frame_span = descriptor.trace.span("construct").arg("frame", frame);
 
                     // update quads
                     // Note: this will happen outside of the library
-                    ui_state.update(worker_start);
-
-                    // request staging and storage buffers
-                    let num_bytes = ui_state.num_quads() as u64 * size_of::<render::Quad>() as u64;
-                    let staging_buffer = buffer_pool.request_staging(num_bytes);
-                    let storage_buffer = buffer_pool.request_storage(num_bytes);
-
-                    // pack quad data into a flat array
-                    let data = ui_state.quads();
-
-                    // prepare staging buffer for writing
-                    let mut view = staging_buffer
-                        .buffer
-                        .slice(0..num_bytes)
-                        .get_mapped_range_mut();
-                    let floats: &mut [f32] = bytemuck::cast_slice_mut(&mut view);
-
-                    // copy data into staging buffer
-                    floats.copy_from_slice(&data);
-                    drop(view);
-                    staging_buffer.buffer.unmap();
-
-                    // copy staging buffer to a storage buffer
-                    staging_to_storage(
-                        staging_buffer,
-                        &storage_buffer,
+                    {
+                        let _span = descriptor.trace.span("update");
+                        ui_state.update(worker_start);
+                        ui_state.apply_shaders(&descriptor.layer_shaders.lock().unwrap());
+                    }
+
+                    // upload the layers that changed since they were last sent to the render thread
+                    {
+                        let _span = descriptor.trace.span("flatten");
+                        ui_state.flatten();
+                    }
+                    let layers = ui_state.layers();
+                    uploaded_versions.resize(layers.len(), None);
+                    let versions: Vec<Option<u64>> = layers
+                        .iter()
+                        .zip(uploaded_versions.iter())
+                        .map(
+                            |(layer, uploaded)| match *uploaded == Some(layer.version()) {
+                                true => None,
+                                false => Some(layer.version()),
+                            },
+                        )
+                        .collect();
+                    let settings: Vec<LayerSettings> =
+                        layers.iter().map(LayerSettings::new).collect();
+                    // a message without changes would only replace a frame the render thread may
+                    // not have taken yet, wait for something to happen instead
+                    if input_time.is_none()
+                        && versions.iter().all(Option::is_none)
+                        && settings.len() == sent_settings.len()
+                        && settings
+                            .iter()
+                            .zip(&sent_settings)
+                            .all(|(a, b)| a.same_as(b))
+                    {
+                        drop(_frame_span);
+                        descriptor.changes.wait(seen_changes, &alive);
+                        continue;
+                    }
+                    let upload_span = descriptor.trace.span("upload");
+                    let buffers = match upload_layers(
+                        layers,
+                        &versions,
+                        &mut buffer_pool,
                         &descriptor.device_arc,
                         &descriptor.queue_arc,
-                        num_bytes,
-                    );
+                        &callback_error,
+                        gpu_timer.as_mut(),
+                    ) {
+                        Ok(buffers) => buffers,
+                        Err(e) => {
+                            result = Err(e);
+                            break;
+                        }
+                    };
+                    drop(upload_span);
+                    let pool_stats = buffer_pool.end_frame();
+                    let flow_id = descriptor.trace.flow_id();
+                    // update statistics (data receive until message sent)
+                    let construct_time = loop_start.elapsed().as_secs_f64();
+                    {
+                        let mut stats = descriptor.stats.lock().unwrap();
+                        stats.construct.update(construct_time);
+                        if stats.pools.len() <= descriptor.index {
+                            stats
+                                .pools
+                                .resize(descriptor.index + 1, PoolStats::default());
+                        }
+                        stats.pools[descriptor.index] = pool_stats;
+                        // copies of earlier frames the GPU has finished by now
+                        if let Some(gpu_timer) = &mut gpu_timer {
+                            for duration in gpu_timer.read() {
+                                stats.gpu_copy.update(duration);
+                            }
+                        }
+                    }
 
-                    // send the storage buffer to the render thread
                     let message = ConstructionWorkerMessage {
-                        storage_buffer,
-                        num_instances: ui_state.num_quads(),
+                        flow_id,
+                        input_time,
+                        layers: layers
+                            .iter()
+                            .zip(buffers)
+                            .map(|(layer, buffers)| LayerUpdate {
+                                blend: layer.blend,
+                                camera: layer.camera,
+                                shader: layer.shader.clone(),
+                                buffers,
+                            })
+                            .collect(),
+                        stats: WorkerStats {
+                            construct_time,
+                            instances: layers.iter().map(|layer| layer.quads().len()).sum(),
+                            pool_bytes: pool_stats.allocated_bytes,
+                        },
                     };
 
-                    // update statistics (data receive until message sent)
-                    stats.update(loop_start.elapsed().as_secs_f64());
-
-                    // send message to the render thread (blocks until the previous message has been consumed)
-                    match sender.try_send(message) {
-                        Ok(_) => {}
-                        Err(mpsc::TrySendError::Full(_)) => {}
-                        Err(mpsc::TrySendError::Disconnected(_)) => {
-                            // send failed, exit the loop and clean up
-                            break;
+                    // hand the message to the render thread
+                    let _span = descriptor.trace.span("send");
+                    // before sending, the render thread may receive the message right away
+                    descriptor.trace.flow_start("frame", flow_id);
+                    if mailbox.put(message) {
+                        descriptor.stats.lock().unwrap().construct.dropped += 1;
+                    }
+                    (descriptor.wake)();
+                    for (uploaded, version) in uploaded_versions.iter_mut().zip(versions) {
+                        if version.is_some() {
+                            *uploaded = version;
                         }
                     }
+                    sent_settings = settings;
+                    if descriptor.pacing == Pacing::Lockstep {
+                        mailbox.wait_taken(&alive);
+                    }
+                }
+
+                // print statistics of the worker's own pool, the window prints the shared ones;
+                // logging may take the GIL, which Python holds while it waits for the stats lock
+                let pool = {
+                    let stats = descriptor.stats.lock().unwrap();
+                    stats
+                        .pools
+                        .get(descriptor.index)
+                        .map(|pool| pool.to_string())
+                };
+                if let Some(pool) = pool {
+                    log::info!("pool ({}): {}", descriptor.name, pool);
+                }
+                let scene = WorkerScene {
+                    ui_state,
+                    start_time: worker_start,
+                };
+                (Some(scene), result)
+            }
+        })
+        .map_err(|e| Error::Worker(format!("failed to spawn thread: {}", e)))?;
+
+    Ok(ConstructionWorker {
+        mailbox,
+        alive,
+        worker_handle: Some(worker_handle),
+        scene_sender,
+        scene: None,
+    })
+}
+
+/// How the construction workers and the render thread hand over frames. The render thread
+/// never waits for a worker, it draws the latest frames it has whenever it redraws.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// the window is redrawn when a worker sent a new frame, a worker that is faster replaces
+    /// frames that were not rendered yet
+    Mailbox,
+    /// every constructed frame is rendered, a worker waits until the render thread took its frame
+    /// before constructing the next one
+    Lockstep,
+    /// like `Mailbox`, but the window is redrawn every display refresh, drawing the last frame
+    /// again if no new one arrived
+    ReuseLast,
+}
+
+pub fn parse_pacing(name: &str) -> Option<Pacing> {
+    match name.to_lowercase().as_str() {
+        "mailbox" => Some(Pacing::Mailbox),
+        "lockstep" => Some(Pacing::Lockstep),
+        "reuse_last" => Some(Pacing::ReuseLast),
+        _ => None,
+    }
+}
+
+/// Time of the earliest input that no constructed frame reflects yet.
+pub type PendingInput = Arc<Mutex<Option<std::time::Instant>>>;
+
+/// Blend mode, camera and shader of a layer, which are sent along with every message.
+struct LayerSettings {
+    blend: render::BlendMode,
+    camera: Option<utils::Transform>,
+    shader: shader::LayerShader,
+}
+
+impl LayerSettings {
+    fn new(layer: &ui::Layer) -> LayerSettings {
+        LayerSettings {
+            blend: layer.blend,
+            camera: layer.camera,
+            shader: layer.shader.clone(),
+        }
+    }
+
+    fn same_as(&self, other: &LayerSettings) -> bool {
+        let same_shader = match (&self.shader.shader, &other.shader.shader) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        self.blend == other.blend
+            && self.camera == other.camera
+            && same_shader
+            && self.shader.uniforms == other.shader.uniforms
+    }
+}
+
+/// How long an idle worker waits for a change before it checks its scene again, which may also
+/// change on its own over time.
+const IDLE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Counts changes the idle workers of a window have to look at: input, shaders set from Python
+/// and frames taken by the render thread.
+#[derive(Clone, Default)]
+pub struct SceneChanges(Arc<(Mutex<u64>, Condvar)>);
+
+impl SceneChanges {
+    pub fn notify(&self) {
+        let (count, changed) = &*self.0;
+        *count.lock().unwrap() += 1;
+        changed.notify_all();
+    }
+
+    fn count(&self) -> u64 {
+        *self.0 .0.lock().unwrap()
+    }
+
+    /// Wait until a change after `seen` is noted, the worker is stopped or the poll interval
+    /// passed.
+    fn wait(&self, seen: u64, alive: &atomic::AtomicBool) {
+        let (count, changed) = &*self.0;
+        let count = count.lock().unwrap();
+        if *count == seen && alive.load(atomic::Ordering::SeqCst) {
+            drop(changed.wait_timeout(count, IDLE_POLL_INTERVAL).unwrap());
+        }
+    }
+}
+
+/// Wakes up the event loop of the window a worker constructs frames for.
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+/// Latest message of a worker, waiting for the render thread to take it.
+#[derive(Default)]
+pub struct Mailbox {
+    message: Mutex<Option<ConstructionWorkerMessage>>,
+    taken: Condvar,
+    /// notified when a message is taken, so that an idle worker looks at its scene again
+    changes: SceneChanges,
+    /// set when the worker thread exits, whether it stopped, failed or panicked
+    closed: atomic::AtomicBool,
+}
+
+/// Closes the mailbox of a worker and wakes the render thread once the worker thread exits.
+struct CloseOnExit {
+    mailbox: Arc<Mailbox>,
+    wake: Waker,
+}
+
+impl Drop for CloseOnExit {
+    fn drop(&mut self) {
+        self.mailbox.closed.store(true, atomic::Ordering::SeqCst);
+        (self.wake)();
+    }
+}
+
+impl Mailbox {
+    /// Whether the worker exited, messages it left before can still be taken.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(atomic::Ordering::SeqCst)
+    }
+
+    pub fn take(&self) -> Option<ConstructionWorkerMessage> {
+        let message = self.message.lock().unwrap().take();
+        if message.is_some() {
+            self.taken.notify_all();
+            self.changes.notify();
+        }
+        message
+    }
+
+    /// Leave a message for the render thread, returns whether it replaced one that was not taken.
+    fn put(&self, mut message: ConstructionWorkerMessage) -> bool {
+        let mut slot = self.message.lock().unwrap();
+        let Some(replaced) = slot.take() else {
+            *slot = Some(message);
+            return false;
+        };
+        carry_over(
+            message.layers.iter_mut().map(|layer| &mut layer.buffers),
+            replaced.layers.into_iter().map(|layer| layer.buffers),
+            |buffers| {
+                for buffer in buffers.into_storage_buffers() {
+                    buffer.ready.store(true, atomic::Ordering::SeqCst);
                 }
+            },
+        );
+        message.input_time = match (message.input_time, replaced.input_time) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        *slot = Some(message);
+        true
+    }
+
+    /// Wait until the render thread took the message or the worker is stopped.
+    fn wait_taken(&self, alive: &atomic::AtomicBool) {
+        let mut slot = self.message.lock().unwrap();
+        while slot.is_some() && alive.load(atomic::Ordering::SeqCst) {
+            // wake up now and then to notice a stop request
+            slot = self
+                .taken
+                .wait_timeout(slot, std::time::Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// Pass the contents of replaced layers on to the layers of a new message that did not change,
+/// the others are recycled, including those the new message has no layer for.
+fn carry_over<'a, T: 'a>(
+    layers: impl IntoIterator<Item = &'a mut Option<T>>,
+    replaced: impl IntoIterator<Item = Option<T>>,
+    mut recycle: impl FnMut(T),
+) {
+    let mut layers = layers.into_iter();
+    for replaced in replaced {
+        match (layers.next(), replaced) {
+            (Some(layer @ None), replaced) => *layer = replaced,
+            (_, Some(replaced)) => recycle(replaced),
+            (_, None) => {}
+        }
+    }
+}
+
+/// Which layers of a window's scene each construction worker builds.
+#[derive(Clone, Copy, PartialEq)]
+pub enum WorkerMode {
+    /// one worker for all layers of the window
+    Window,
+    /// one worker per layer, so that a heavy layer does not hold up the others
+    Layer,
+}
+
+pub fn parse_worker_mode(name: &str) -> Option<WorkerMode> {
+    match name.to_lowercase().as_str() {
+        "window" => Some(WorkerMode::Window),
+        "layer" => Some(WorkerMode::Layer),
+        _ => None,
+    }
+}
+
+/// UI state of a worker, handed to a new worker when the device is recreated.
+pub struct WorkerScene {
+    ui_state: ui::UIState,
+    start_time: std::time::Instant,
+}
 
-                // print statistics
-                println!("├─ construct : {}", stats);
+impl WorkerScene {
+    /// Set up the example scene, split into the scenes of the workers.
+    /// Note: this will happen outside of the library
+    pub fn setup(mode: WorkerMode) -> Vec<WorkerScene> {
+        let mut ui_state = ui::UIState::new();
+        ui_state.setup();
+        let ui_states = match mode {
+            WorkerMode::Window => vec![ui_state],
+            WorkerMode::Layer => ui_state.split_layers(),
+        };
+        let start_time = std::time::Instant::now();
+        ui_states
+            .into_iter()
+            .map(|ui_state| WorkerScene {
+                ui_state,
+                start_time,
             })
-            .expect("failed to spawn construction worker"),
+            .collect()
+    }
+
+    /// Time animations are evaluated from.
+    pub fn start_time(&self) -> std::time::Instant {
+        self.start_time
+    }
+
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.ui_state
+            .layers()
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect()
     }
 }
 
@@ -286,19 +1234,343 @@ pub struct ConstructionWorkerDescriptor {
     pub device_arc: Arc<wgpu::Device>,
     pub queue_arc: Arc<wgpu::Queue>,
     pub bind_group_layout: wgpu::BindGroupLayout,
+    /// names the worker thread
+    pub name: String,
+    /// position among the workers of the window
+    pub index: usize,
+    pub layer_shaders: shader::LayerShaders,
+    /// called after each message and when the worker exits, to schedule a redraw
+    pub wake: Waker,
+    pub pacing: Pacing,
+    pub pending_input: PendingInput,
+    pub changes: SceneChanges,
+    pub stats: utils::SharedStats,
+    pub trace: trace::Tracer,
+    /// bytes the worker's buffer pool may take up, `None` for no limit
+    pub pool_budget: Option<u64>,
 }
 
 pub struct ConstructionWorker {
-    pub receiver: mpsc::Receiver<ConstructionWorkerMessage>,
+    pub mailbox: Arc<Mailbox>,
     alive: Arc<atomic::AtomicBool>,
-    worker_handle: std::thread::JoinHandle<()>,
+    worker_handle: Option<std::thread::JoinHandle<(Option<WorkerScene>, Result<()>)>>,
+    scene_sender: mpsc::Sender<WorkerScene>,
+    scene: Option<WorkerScene>,
 }
 
 impl ConstructionWorker {
-    pub fn stop_and_join(self) {
+    /// Hand the worker its scene, it starts constructing frames once it has one.
+    pub fn start(&mut self, scene: WorkerScene) {
+        // the thread only exits early if it panicked, the scene then stays here
+        if let Err(mpsc::SendError(scene)) = self.scene_sender.send(scene) {
+            self.scene = Some(scene);
+        }
+    }
+
+    /// Wait for the worker thread to exit, returns the error that stopped it.
+    pub fn join(&mut self) -> Result<()> {
+        match self.worker_handle.take().map(|handle| handle.join()) {
+            None => Ok(()),
+            Some(Ok((scene, result))) => {
+                self.scene = scene.or(self.scene.take());
+                result
+            }
+            Some(Err(_)) => Err(Error::Worker("worker thread panicked".to_string())),
+        }
+    }
+
+    /// Ask the worker to stop after the frame it is constructing.
+    pub fn stop(&self) {
         self.alive.store(false, atomic::Ordering::SeqCst);
-        self.worker_handle
-            .join()
-            .expect("failed to join construction worker thread");
+    }
+
+    /// Stop the worker and return its scene, which is lost if the worker panicked.
+    pub fn stop_and_join(mut self) -> (Option<WorkerScene>, Result<()>) {
+        self.stop();
+        let result = self.join();
+        (self.scene.take(), result)
+    }
+}
+
+impl Drop for ConstructionWorker {
+    // workers of a renderer that failed to start are stopped as well
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct TestBuffer(Arc<atomic::AtomicBool>);
+
+    impl PoolBuffer for TestBuffer {
+        fn ready(&self) -> &atomic::AtomicBool {
+            &self.0
+        }
+    }
+
+    /// Buckets holding a buffer of each size class, ready or in use.
+    fn buckets(buffers: &[(u64, bool)]) -> Buckets<TestBuffer> {
+        let mut buckets = Buckets::default();
+        for &(class, ready) in buffers {
+            buckets.push(class, TestBuffer(Arc::new(ready.into())));
+        }
+        buckets
+    }
+
+    fn classes(buckets: &Buckets<TestBuffer>) -> Vec<(u64, usize)> {
+        buckets
+            .0
+            .iter()
+            .map(|(class, bucket)| (*class, bucket.buffers.len()))
+            .collect()
+    }
+
+    #[test]
+    fn size_classes() {
+        assert_eq!(size_class(1, 1 << 20), MIN_SIZE_CLASS);
+        assert_eq!(size_class(1024, 1 << 20), 1024);
+        assert_eq!(size_class(1025, 1 << 20), 2048);
+        assert_eq!(size_class(300_000, 1 << 20), 1 << 19);
+        // never beyond the largest buffer of the device
+        assert_eq!(size_class(600_000, 500_000), 500_000);
+    }
+
+    #[test]
+    fn take_smallest_fitting_buffer() {
+        let buckets = buckets(&[(1024, true), (4096, true), (2048, false), (2048, true)]);
+        let buffer = buckets.take_ready(2048).unwrap();
+        assert!(!buffer.is_ready());
+        // the ready buffer of the class was taken, only the larger one is left
+        assert!(buckets.take_ready(2048).is_some());
+        assert!(buckets.take_ready(2048).is_none());
+        assert!(buckets.any_in_use(2048));
+        assert!(buckets.take_ready(1024).is_some());
+    }
+
+    #[test]
+    fn fitting_classes_are_limited() {
+        let buckets = buckets(&[(1024, true), (8192, true)]);
+        // up to four times the size class, so that small layers leave large buffers alone
+        assert_eq!(buckets.fitting(1024).count(), 1);
+        assert_eq!(buckets.fitting(2048).count(), 1);
+        assert_eq!(buckets.fitting(4096).count(), 1);
+        assert!(buckets.take_ready(16384).is_none());
+        assert!(!buckets.any_in_use(16384));
+        let buffer = buckets.take_ready(2048).unwrap();
+        assert!(!buffer.is_ready());
+        assert!(buckets.any_in_use(4096));
+    }
+
+    #[test]
+    fn allocated_by_size_class() {
+        let buckets = buckets(&[(1024, true), (1024, false), (4096, true)]);
+        assert_eq!(buckets.allocated_bytes(), 6144);
+        assert_eq!(buckets.len(), 3);
+    }
+
+    fn message(layers: usize, input_time: Option<std::time::Instant>) -> ConstructionWorkerMessage {
+        ConstructionWorkerMessage {
+            flow_id: 0,
+            input_time,
+            layers: (0..layers)
+                .map(|_| LayerUpdate {
+                    blend: render::BlendMode::Alpha,
+                    camera: None,
+                    shader: shader::LayerShader::default(),
+                    buffers: None,
+                })
+                .collect(),
+            stats: WorkerStats::default(),
+        }
+    }
+
+    #[test]
+    fn put_replaces_message() {
+        let mailbox = Mailbox::default();
+        let earlier = std::time::Instant::now();
+        let later = earlier + std::time::Duration::from_millis(5);
+        assert!(!mailbox.put(message(2, Some(later))));
+        assert!(mailbox.put(message(1, Some(earlier))));
+        assert!(mailbox.put(message(1, None)));
+        let taken = mailbox.take().unwrap();
+        assert_eq!(taken.layers.len(), 1);
+        // the earliest input is reflected by the frame that replaced it
+        assert!(taken.input_time == Some(earlier));
+        assert!(mailbox.take().is_none());
+        assert!(!mailbox.put(message(1, None)));
+    }
+
+    #[test]
+    fn take_notes_change() {
+        let mailbox = Mailbox::default();
+        mailbox.put(message(1, None));
+        let seen = mailbox.changes.count();
+        mailbox.take();
+        assert_eq!(mailbox.changes.count(), seen + 1);
+        // nothing to take
+        mailbox.take();
+        assert_eq!(mailbox.changes.count(), seen + 1);
+    }
+
+    #[test]
+    fn carry_over_unchanged_layers() {
+        let mut layers = vec![None, Some(10), None];
+        let mut recycled = Vec::new();
+        carry_over(&mut layers, [Some(1), Some(2), None], |buffers| {
+            recycled.push(buffers)
+        });
+        assert_eq!(layers, [Some(1), Some(10), None]);
+        assert_eq!(recycled, [2]);
+    }
+
+    #[test]
+    fn carry_over_recycles_removed_layers() {
+        let mut layers = vec![None];
+        let mut recycled = Vec::new();
+        carry_over(&mut layers, [None, Some(2), Some(3)], |buffers| {
+            recycled.push(buffers)
+        });
+        assert_eq!(layers, [None]);
+        assert_eq!(recycled, [2, 3]);
+    }
+
+    #[test]
+    fn carry_over_into_added_layers() {
+        let mut layers = vec![None, Some(10), None];
+        let mut recycled = Vec::new();
+        carry_over(&mut layers, [Some(1)], |buffers| recycled.push(buffers));
+        assert_eq!(layers, [Some(1), Some(10), None]);
+        assert!(recycled.is_empty());
+    }
+
+    fn part(
+        region: usize,
+        region_offset: u64,
+        staging: usize,
+        offset: u64,
+        num_bytes: u64,
+    ) -> StagedPart {
+        StagedPart {
+            region,
+            region_offset,
+            staging,
+            offset,
+            num_bytes,
+        }
+    }
+
+    #[test]
+    fn staging_without_split() {
+        let (sizes, parts) = plan_staging([100, 0, 50], 1024);
+        assert_eq!(sizes, [150]);
+        assert_eq!(parts, [part(0, 0, 0, 0, 100), part(2, 0, 0, 100, 50)]);
+    }
+
+    #[test]
+    fn staging_splits_regions() {
+        let (sizes, parts) = plan_staging([600, 600], 1000);
+        assert_eq!(sizes, [1000, 200]);
+        assert_eq!(
+            parts,
+            [
+                part(0, 0, 0, 0, 600),
+                part(1, 0, 0, 600, 400),
+                part(1, 400, 1, 0, 200)
+            ]
+        );
+    }
+
+    #[test]
+    fn staging_of_region_larger_than_buffers() {
+        let (sizes, parts) = plan_staging([250, 100], 100);
+        assert_eq!(sizes, [100, 100, 100, 50]);
+        assert_eq!(
+            parts,
+            [
+                part(0, 0, 0, 0, 100),
+                part(0, 100, 1, 0, 100),
+                part(0, 200, 2, 0, 50),
+                part(1, 0, 2, 50, 50),
+                part(1, 50, 3, 0, 50)
+            ]
+        );
+    }
+
+    #[test]
+    fn staging_nothing() {
+        let (sizes, parts) = plan_staging([0, 0], 100);
+        assert!(sizes.is_empty() && parts.is_empty());
+    }
+
+    #[test]
+    fn excess_over_budget() {
+        assert_eq!(excess_bytes(None, 1 << 30, 1 << 30), 0);
+        assert_eq!(excess_bytes(Some(4096), 2048, 2048), 0);
+        assert_eq!(excess_bytes(Some(4096), 2048, 4096), 2048);
+        // already beyond the budget
+        assert_eq!(excess_bytes(Some(1024), 4096, 1024), 4096);
+    }
+
+    #[test]
+    fn release_largest_ready_first() {
+        let mut buckets = buckets(&[(1024, true), (4096, true), (8192, false), (2048, true)]);
+        assert_eq!(buckets.release(3000), (1, 4096));
+        assert_eq!(classes(&buckets), [(1024, 1), (2048, 1), (8192, 1)]);
+        // buffers in use are kept even if that releases less than asked for
+        assert_eq!(buckets.release(1 << 20), (2, 3072));
+        assert_eq!(classes(&buckets), [(8192, 1)]);
+    }
+
+    #[test]
+    fn release_nothing() {
+        let mut buckets = buckets(&[(1024, true)]);
+        assert_eq!(buckets.release(0), (0, 0));
+        assert_eq!(classes(&buckets), [(1024, 1)]);
+    }
+
+    #[test]
+    fn trim_to_peak_usage() {
+        let mut buckets = buckets(&[
+            (1024, false),
+            (1024, false),
+            (1024, true),
+            (1024, true),
+            (1024, true),
+            (4096, true),
+        ]);
+        assert_eq!(buckets.record_usage(), 2048);
+        // two in use and a spare one stay, the unused class goes
+        assert_eq!(buckets.trim(), (3, 1));
+        assert_eq!(classes(&buckets), [(1024, 3)]);
+        assert_eq!(buckets.allocated_bytes(), 3072);
+    }
+
+    #[test]
+    fn trim_keeps_buffers_in_use() {
+        let mut buckets = buckets(&[(1024, true)]);
+        buckets.record_usage();
+        // taken after the usage was recorded, so the class looks unused
+        buckets.take_ready(1024).unwrap();
+        assert_eq!(buckets.trim(), (0, 0));
+        assert_eq!(classes(&buckets), [(1024, 1)]);
+    }
+
+    #[test]
+    fn trim_forgets_peak() {
+        let mut buckets = buckets(&[(1024, false), (1024, true), (1024, true)]);
+        buckets.record_usage();
+        assert_eq!(buckets.trim(), (1, 0));
+        for bucket in buckets.0.values() {
+            for buffer in bucket.buffers.iter() {
+                buffer.0.store(true, atomic::Ordering::SeqCst);
+            }
+        }
+        buckets.record_usage();
+        assert_eq!(buckets.trim(), (2, 1));
+        assert!(classes(&buckets).is_empty());
     }
 }
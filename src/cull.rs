@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use crate::construct;
+use crate::render;
+use bytemuck::NoUninit;
+use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+/// Quads below which a layer is drawn directly, culling it would cost more than it saves.
+const MIN_INSTANCES: u32 = 16384;
+/// Instances handled by one workgroup, `WORKGROUP_SIZE` in the shader.
+const WORKGROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct Block {
+    start: u32,
+    end: u32,
+    count: u32,
+    offset: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct Range {
+    first_block: u32,
+    end_block: u32,
+}
+
+/// Compute passes that cull the quads of a layer against the viewport and compact the visible
+/// ones into a separate buffer, which is then drawn with `draw_indirect`.
+pub struct CullPipeline {
+    device_arc: Arc<wgpu::Device>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    compact_pipeline: wgpu::ComputePipeline,
+}
+
+impl CullPipeline {
+    /// Returns `None` if the device was created without `INDIRECT_FIRST_INSTANCE`, which is
+    /// needed to draw the ranges of a layer from one compacted buffer.
+    pub fn new(device_arc: Arc<wgpu::Device>) -> Option<CullPipeline> {
+        if !device_arc
+            .features()
+            .contains(wgpu::Features::INDIRECT_FIRST_INSTANCE)
+        {
+            log::info!("indirect drawing is not supported, quads are drawn without culling");
+            return None;
+        }
+
+        let shader = device_arc.create_shader_module(include_wgsl!("cull_shader.wgsl"));
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("cull bind group layout"),
+                entries: &[
+                    storage_entry(0, true),
+                    storage_entry(1, false),
+                    storage_entry(2, true),
+                    storage_entry(3, false),
+                    storage_entry(4, false),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let pipeline_layout = device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cull pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point| {
+            device_arc.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let count_pipeline = create_pipeline("cull count pipeline", "count");
+        let scan_pipeline = create_pipeline("cull scan pipeline", "scan");
+        let compact_pipeline = create_pipeline("cull compact pipeline", "compact");
+
+        Some(CullPipeline {
+            device_arc,
+            bind_group_layout,
+            count_pipeline,
+            scan_pipeline,
+            compact_pipeline,
+        })
+    }
+
+    /// Set up culling for new contents of a layer, read from `source`, reusing the compacted buffer of `previous`
+    /// if it is large enough. Returns `None` if the layer is drawn directly.
+    pub fn prepare(
+        &self,
+        source: &construct::StorageBuffer,
+        draw_ranges: &[render::DrawRange],
+        camera_buffer: &wgpu::Buffer,
+        quad_bind_group_layout: &wgpu::BindGroupLayout,
+        previous: Option<CulledQuads>,
+    ) -> Option<CulledQuads> {
+        // every range is split into blocks of its own, so that its visible quads end up together
+        let mut blocks = Vec::new();
+        let mut ranges = Vec::new();
+        for range in draw_ranges.iter() {
+            let first_block = blocks.len() as u32;
+            for start in (range.start..range.end).step_by(WORKGROUP_SIZE as usize) {
+                blocks.push(Block {
+                    start,
+                    end: (start + WORKGROUP_SIZE).min(range.end),
+                    count: 0,
+                    offset: 0,
+                });
+            }
+            ranges.push(Range {
+                first_block,
+                end_block: blocks.len() as u32,
+            });
+        }
+        let num_instances: u32 = draw_ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum();
+        // the blocks are dispatched along one dimension
+        let max_blocks = self
+            .device_arc
+            .limits()
+            .max_compute_workgroups_per_dimension;
+        if num_instances < MIN_INSTANCES || blocks.len() as u32 > max_blocks {
+            return None;
+        }
+
+        // never more than the quads of `source`, which fit into a storage buffer
+        let num_bytes = num_instances as u64 * size_of::<render::Quad>() as u64;
+        let quads = match previous {
+            Some(previous) if previous.quads.buffer.size() >= num_bytes => previous.quads,
+            _ => construct::create_storage_buffer(
+                &self.device_arc,
+                quad_bind_group_layout,
+                num_bytes
+                    .next_power_of_two()
+                    .min(construct::max_storage_size(&self.device_arc)),
+            ),
+        };
+        let block_buffer = self
+            .device_arc
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("cull block buffer"),
+                contents: bytemuck::cast_slice(&blocks),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let range_buffer = self
+            .device_arc
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("cull range buffer"),
+                contents: bytemuck::cast_slice(&ranges),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let draw_args = self.device_arc.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("cull draw args buffer"),
+            size: (ranges.len() * size_of::<wgpu::util::DrawIndirectArgs>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let bind_group = self
+            .device_arc
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("cull bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: source.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: block_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: range_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: quads.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: draw_args.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: camera_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+
+        Some(CulledQuads {
+            quads,
+            draw_args,
+            bind_group,
+            num_blocks: blocks.len() as u32,
+            stale: true,
+        })
+    }
+
+    /// Recompute the visible quads of a layer.
+    pub fn cull(&self, compute_pass: &mut wgpu::ComputePass, culled: &CulledQuads) {
+        compute_pass.set_bind_group(0, &culled.bind_group, &[]);
+        compute_pass.set_pipeline(&self.count_pipeline);
+        compute_pass.dispatch_workgroups(culled.num_blocks, 1, 1);
+        compute_pass.set_pipeline(&self.scan_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+        compute_pass.set_pipeline(&self.compact_pipeline);
+        compute_pass.dispatch_workgroups(culled.num_blocks, 1, 1);
+    }
+}
+
+/// Visible quads of a layer, with the draw arguments of each of its draw ranges.
+pub struct CulledQuads {
+    pub quads: construct::StorageBuffer,
+    draw_args: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    num_blocks: u32,
+    /// set when the camera or contents of the layer changed since the quads were last culled
+    pub stale: bool,
+}
+
+impl CulledQuads {
+    /// Draw the visible quads of the draw range with index `range`.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, range: usize) {
+        let offset = range * size_of::<wgpu::util::DrawIndirectArgs>();
+        render_pass.draw_indirect(&self.draw_args, offset as u64);
+    }
+}
@@ -0,0 +1,134 @@
+struct Quad {
+    center: vec2<f32>,
+    size: vec2<f32>,
+    color: vec4<f32>,
+    // column-major 2x2 matrix applied around the center
+    transform: vec4<f32>,
+    corner_radius: f32,
+    blur: f32,
+    flags: u32,
+    _padding: u32,
+};
+
+// view transform of the layer
+struct Camera {
+    transform: vec4<f32>,
+    translation: vec2<f32>,
+};
+
+// instances of a draw range handled by one workgroup
+struct Block {
+    start: u32,
+    end: u32,
+    // number of visible instances and index of the first one in the compacted buffer
+    count: u32,
+    offset: u32,
+};
+
+// consecutive blocks making up a draw range
+struct Range {
+    first_block: u32,
+    end_block: u32,
+};
+
+// arguments of draw_indirect
+struct DrawArgs {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+
+@group(0) @binding(0) var<storage, read> quads: array<Quad>;
+@group(0) @binding(1) var<storage, read_write> blocks: array<Block>;
+@group(0) @binding(2) var<storage, read> ranges: array<Range>;
+@group(0) @binding(3) var<storage, read_write> visible_quads: array<Quad>;
+@group(0) @binding(4) var<storage, read_write> draw_args: array<DrawArgs>;
+@group(0) @binding(5) var<uniform> camera: Camera;
+
+var<workgroup> block_count: atomic<u32>;
+var<workgroup> scratch: array<u32, WORKGROUP_SIZE>;
+
+// whether any part of the quad, including its blurred edges, ends up inside the viewport
+fn is_visible(quad: Quad) -> bool {
+    let extent = quad.size * 0.5 + vec2<f32>(quad.blur * 1.5);
+    let camera_transform = mat2x2<f32>(camera.transform.xy, camera.transform.zw);
+    let transform = camera_transform * mat2x2<f32>(quad.transform.xy, quad.transform.zw);
+    let center = camera_transform * quad.center + camera.translation;
+    // half size of the bounding box of the transformed quad
+    let half_size = abs(transform[0]) * extent.x + abs(transform[1]) * extent.y;
+    return all(center - half_size <= vec2<f32>(1.0)) && all(center + half_size >= vec2<f32>(-1.0));
+}
+
+fn visible_at(block: Block, local: u32) -> bool {
+    let index = block.start + local;
+    return index < block.end && is_visible(quads[index]);
+}
+
+// inclusive prefix sum over the values of all invocations of the workgroup
+fn inclusive_scan(local: u32, value: u32) -> u32 {
+    scratch[local] = value;
+    workgroupBarrier();
+    for (var stride = 1u; stride < WORKGROUP_SIZE; stride *= 2u) {
+        var sum = scratch[local];
+        if local >= stride {
+            sum += scratch[local - stride];
+        }
+        workgroupBarrier();
+        scratch[local] = sum;
+        workgroupBarrier();
+    }
+    return scratch[local];
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn count(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    if visible_at(blocks[workgroup_id.x], local) {
+        atomicAdd(&block_count, 1u);
+    }
+    workgroupBarrier();
+    if local == 0u {
+        blocks[workgroup_id.x].count = atomicLoad(&block_count);
+    }
+}
+
+// runs as a single workgroup, each invocation takes care of a contiguous chunk of blocks
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn scan(@builtin(local_invocation_index) local: u32) {
+    let num_blocks = arrayLength(&blocks);
+    let chunk = (num_blocks + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+    let first = min(local * chunk, num_blocks);
+    let end = min(first + chunk, num_blocks);
+
+    var sum = 0u;
+    for (var i = first; i < end; i++) {
+        sum += blocks[i].count;
+    }
+    var offset = inclusive_scan(local, sum) - sum;
+    for (var i = first; i < end; i++) {
+        blocks[i].offset = offset;
+        offset += blocks[i].count;
+    }
+    storageBarrier();
+
+    // the visible instances of a range follow each other, as its blocks do
+    for (var i = local; i < arrayLength(&ranges); i += WORKGROUP_SIZE) {
+        let range = ranges[i];
+        let first_block = blocks[range.first_block];
+        let last_block = blocks[range.end_block - 1u];
+        draw_args[i] = DrawArgs(4u, last_block.offset + last_block.count - first_block.offset, 0u, first_block.offset);
+    }
+}
+
+// copies the visible quads to the compacted buffer, keeping their order
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn compact(@builtin(workgroup_id) workgroup_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let block = blocks[workgroup_id.x];
+    let visible = visible_at(block, local);
+    let index = inclusive_scan(local, select(0u, 1u, visible));
+    if visible {
+        visible_quads[block.offset + index - 1u] = quads[block.start + local];
+    }
+}
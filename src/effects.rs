@@ -0,0 +1,358 @@
+use std::sync::Arc;
+
+use crate::render;
+use bytemuck::NoUninit;
+use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+pub struct BlurSettings {
+    /// standard deviation of the gaussian kernel in pixels
+    pub sigma: f32,
+    /// number of horizontal + vertical blur passes
+    pub passes: u32,
+}
+
+impl Default for BlurSettings {
+    fn default() -> Self {
+        BlurSettings {
+            sigma: 6.0,
+            passes: 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct BlurParams {
+    direction: [f32; 2],
+    sigma: f32,
+    radius: f32,
+}
+
+struct BlurTargets {
+    width: u32,
+    height: u32,
+    scene_view: wgpu::TextureView,
+    // multisampled scene texture, resolved into the scene texture
+    scene_msaa_view: Option<wgpu::TextureView>,
+    ping_view: wgpu::TextureView,
+    pong_view: wgpu::TextureView,
+    // scene texture, blurred horizontally
+    scene_bind_group: wgpu::BindGroup,
+    // ping texture, blurred vertically
+    ping_bind_group: wgpu::BindGroup,
+    // pong texture, blurred horizontally
+    pong_bind_group: wgpu::BindGroup,
+    // final blur result, sampled by the quad shader
+    composite_bind_group: wgpu::BindGroup,
+}
+
+/// Post-processing stage that renders the scene offscreen and blurs it, so that
+/// translucent panels can show a frosted version of whatever lies behind them.
+pub struct BackdropBlur {
+    device_arc: Arc<wgpu::Device>,
+    texture_format: wgpu::TextureFormat,
+    sample_count: u32,
+    settings: BlurSettings,
+    blur_pipeline: wgpu::RenderPipeline,
+    blit_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    targets: Option<BlurTargets>,
+}
+
+impl BackdropBlur {
+    pub fn new(
+        device_arc: Arc<wgpu::Device>,
+        texture_format: wgpu::TextureFormat,
+        sample_count: u32,
+        composite_bind_group_layout: wgpu::BindGroupLayout,
+        settings: BlurSettings,
+    ) -> BackdropBlur {
+        let shader = device_arc.create_shader_module(include_wgsl!("blur_shader.wgsl"));
+        let bind_group_layout =
+            device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("blur bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("blur pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point, sample_count| {
+            device_arc.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: texture_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        let blur_pipeline = create_pipeline("blur pipeline", "fs_blur", 1);
+        // the blit is drawn into the final (possibly multisampled) render pass
+        let blit_pipeline = create_pipeline("blit pipeline", "fs_blit", sample_count);
+
+        let sampler = device_arc.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("blur sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        BackdropBlur {
+            device_arc,
+            texture_format,
+            sample_count,
+            settings,
+            blur_pipeline,
+            blit_pipeline,
+            bind_group_layout,
+            composite_bind_group_layout,
+            sampler,
+            targets: None,
+        }
+    }
+
+    /// Make sure the offscreen textures match the size of the output.
+    pub fn prepare(&mut self, width: u32, height: u32) {
+        match &self.targets {
+            Some(targets) if targets.width == width && targets.height == height => {}
+            _ => self.targets = Some(self.create_targets(width, height)),
+        }
+    }
+
+    /// Target the scene has to be rendered into before calling `blur`.
+    pub fn scene_target(&self) -> render::RenderTarget<'_> {
+        let targets = self.targets();
+        match &targets.scene_msaa_view {
+            Some(scene_msaa_view) => render::RenderTarget {
+                view: scene_msaa_view,
+                resolve_target: Some(&targets.scene_view),
+            },
+            None => render::RenderTarget {
+                view: &targets.scene_view,
+                resolve_target: None,
+            },
+        }
+    }
+
+    /// Bind group exposing the blurred scene to the quad shader.
+    pub fn composite_bind_group(&self) -> &wgpu::BindGroup {
+        &self.targets().composite_bind_group
+    }
+
+    /// Run the separable blur passes on the rendered scene.
+    pub fn blur(&self, encoder: &mut wgpu::CommandEncoder) {
+        let targets = self.targets();
+        for i in 0..self.settings.passes.max(1) {
+            let horizontal = match i {
+                0 => &targets.scene_bind_group,
+                _ => &targets.pong_bind_group,
+            };
+            self.fullscreen_pass(encoder, &targets.ping_view, &self.blur_pipeline, horizontal);
+            self.fullscreen_pass(
+                encoder,
+                &targets.pong_view,
+                &self.blur_pipeline,
+                &targets.ping_bind_group,
+            );
+        }
+    }
+
+    /// Copy the unblurred scene into the current render pass.
+    pub fn blit(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.blit_pipeline);
+        render_pass.set_bind_group(0, &self.targets().scene_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn fullscreen_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("blur pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn targets(&self) -> &BlurTargets {
+        self.targets
+            .as_ref()
+            .expect("backdrop blur used before prepare")
+    }
+
+    fn create_targets(&self, width: u32, height: u32) -> BlurTargets {
+        let create_view = |label, sample_count| {
+            self.device_arc
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.texture_format,
+                    usage: match sample_count {
+                        1 => {
+                            wgpu::TextureUsages::RENDER_ATTACHMENT
+                                | wgpu::TextureUsages::TEXTURE_BINDING
+                        }
+                        _ => wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    },
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let scene_view = create_view("backdrop scene texture", 1);
+        let scene_msaa_view = match self.sample_count {
+            1 => None,
+            count => Some(create_view("backdrop multisampled scene texture", count)),
+        };
+        let ping_view = create_view("backdrop ping texture", 1);
+        let pong_view = create_view("backdrop pong texture", 1);
+
+        let create_params = |label, direction| {
+            let params = BlurParams {
+                direction,
+                sigma: self.settings.sigma,
+                radius: (self.settings.sigma * 3.0).ceil().min(32.0),
+            };
+            self.device_arc
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents: bytemuck::bytes_of(&params),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                })
+        };
+        let horizontal = create_params("horizontal blur params", [1.0 / width as f32, 0.0]);
+        let vertical = create_params("vertical blur params", [0.0, 1.0 / height as f32]);
+
+        let create_bind_group = |view, params: &wgpu::Buffer| {
+            self.device_arc
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("blur bind group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&self.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: params.as_entire_binding(),
+                        },
+                    ],
+                })
+        };
+        let scene_bind_group = create_bind_group(&scene_view, &horizontal);
+        let ping_bind_group = create_bind_group(&ping_view, &vertical);
+        let pong_bind_group = create_bind_group(&pong_view, &horizontal);
+
+        let composite_bind_group = self
+            .device_arc
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("backdrop composite bind group"),
+                layout: &self.composite_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&pong_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+        BlurTargets {
+            width,
+            height,
+            scene_view,
+            scene_msaa_view,
+            ping_view,
+            pong_view,
+            scene_bind_group,
+            ping_bind_group,
+            pong_bind_group,
+            composite_bind_group,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use pyo3::create_exception;
+use pyo3::PyErr;
+
+create_exception!(guilible, GuilibleError, pyo3::exceptions::PyException);
+create_exception!(guilible, AdapterError, GuilibleError);
+create_exception!(guilible, DeviceError, GuilibleError);
+create_exception!(guilible, SurfaceError, GuilibleError);
+create_exception!(guilible, UnsupportedSurfaceError, SurfaceError);
+create_exception!(guilible, WindowError, GuilibleError);
+create_exception!(guilible, WorkerError, GuilibleError);
+create_exception!(guilible, ShaderError, GuilibleError);
+
+#[derive(Debug)]
+pub enum Error {
+    EventLoop(winit::error::EventLoopError),
+    Window(winit::error::OsError),
+    CreateSurface(wgpu::CreateSurfaceError),
+    Surface(wgpu::SurfaceError),
+    /// no adapter that can present to the window surface
+    NoAdapter,
+    /// the adapter of the shared device cannot present to the surface of a window
+    UnsupportedSurface,
+    Device(wgpu::RequestDeviceError),
+    /// a staging buffer could not be mapped again, usually because the device was lost
+    BufferMap(wgpu::BufferAsyncError),
+    /// a layer needs a larger storage buffer than the device can bind
+    BufferSize {
+        layer: String,
+        bytes: u64,
+        limit: u64,
+    },
+    Worker(String),
+    Shader(String),
+    /// exception raised by a Python callback
+    Callback(PyErr),
+    /// the trace file could not be created or written
+    Trace(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::EventLoop(e) => write!(f, "failed to create event loop: {}", e),
+            Error::Window(e) => write!(f, "failed to create window: {}", e),
+            Error::CreateSurface(e) => write!(f, "failed to create surface: {}", e),
+            Error::Surface(e) => write!(f, "surface error: {}", e),
+            Error::NoAdapter => {
+                write!(f, "failed to find a GPU adapter compatible with the window")
+            }
+            Error::UnsupportedSurface => {
+                write!(f, "the GPU adapter cannot present to the window surface")
+            }
+            Error::Device(e) => write!(f, "failed to create device: {}", e),
+            Error::BufferMap(e) => write!(f, "failed to re-map staging buffer: {}", e),
+            Error::BufferSize {
+                layer,
+                bytes,
+                limit,
+            } => write!(
+                f,
+                "layer '{}' needs a buffer of {} bytes, the device supports at most {} bytes",
+                layer, bytes, limit
+            ),
+            Error::Worker(message) => write!(f, "construction worker failed: {}", message),
+            Error::Shader(message) => write!(f, "failed to compile shader:\n{}", message),
+            Error::Callback(e) => write!(f, "callback raised an exception: {}", e),
+            Error::Trace(e) => write!(f, "failed to write trace: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> PyErr {
+        let message = error.to_string();
+        match error {
+            Error::EventLoop(_) | Error::Window(_) => WindowError::new_err(message),
+            Error::CreateSurface(_) | Error::Surface(_) => SurfaceError::new_err(message),
+            Error::NoAdapter => AdapterError::new_err(message),
+            Error::UnsupportedSurface => UnsupportedSurfaceError::new_err(message),
+            Error::Device(_) | Error::BufferMap(_) | Error::BufferSize { .. } => {
+                DeviceError::new_err(message)
+            }
+            Error::Worker(_) => WorkerError::new_err(message),
+            Error::Shader(_) => ShaderError::new_err(message),
+            // re-raise the original exception
+            Error::Callback(e) => e,
+            Error::Trace(_) => pyo3::exceptions::PyOSError::new_err(message),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+
+use crate::construct;
+use crate::render::Quad;
+use crate::utils::Color;
+
+/// Number of frames shown in the frame time graph and averaged for the numbers.
+const HISTORY: usize = 120;
+/// Size of a font pixel in screen pixels.
+const SCALE: f32 = 2.0;
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+const LINE_HEIGHT: f32 = 7.0 * SCALE;
+const GRAPH_HEIGHT: f32 = 48.0;
+const BAR_WIDTH: f32 = 2.0;
+/// Frame time at the top of the graph, in seconds.
+const GRAPH_MAX: f64 = 1.0 / 20.0;
+
+const BACKGROUND: Color = Color {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.7,
+};
+const TEXT: Color = Color {
+    r: 0.9,
+    g: 0.9,
+    b: 0.9,
+    a: 1.0,
+};
+const RENDER_BAR: Color = Color {
+    r: 0.3,
+    g: 0.6,
+    b: 1.0,
+    a: 0.9,
+};
+
+/// Glyph of the built-in 3x5 pixel font, one row per entry with the leftmost pixel as highest bit.
+/// Only covers the characters the overlay uses, others are drawn as blanks.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        _ => [0; 5],
+    }
+}
+
+/// Color of a frame time bar, green up to 60 FPS, yellow up to 30 FPS and red below.
+fn frame_color(frame_time: f64) -> Color {
+    match frame_time {
+        t if t <= 1.05 / 60.0 => Color {
+            r: 0.3,
+            g: 0.9,
+            b: 0.4,
+            a: 0.6,
+        },
+        t if t <= 1.05 / 30.0 => Color {
+            r: 1.0,
+            g: 0.8,
+            b: 0.2,
+            a: 0.6,
+        },
+        _ => Color {
+            r: 1.0,
+            g: 0.3,
+            b: 0.3,
+            a: 0.6,
+        },
+    }
+}
+
+/// Frame statistics overlay, laid out in pixels and converted to quads in NDC.
+#[derive(Default)]
+pub struct Hud {
+    /// (frame time, render time) of the last frames in seconds
+    frames: VecDeque<(f64, f64)>,
+    construct_times: VecDeque<f64>,
+}
+
+impl Hud {
+    pub fn record_frame(&mut self, frame_time: f64, render_time: f64) {
+        if self.frames.len() == HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((frame_time, render_time));
+    }
+
+    pub fn record_worker(&mut self, stats: &construct::WorkerStats) {
+        if self.construct_times.len() == HISTORY {
+            self.construct_times.pop_front();
+        }
+        self.construct_times.push_back(stats.construct_time);
+    }
+
+    /// Quads of the overlay for a target of `size` pixels.
+    pub fn quads(&self, worker: &construct::WorkerStats, size: (u32, u32)) -> Vec<Quad> {
+        let frame_time = mean(self.frames.iter().map(|frame| frame.0));
+        let render_time = mean(self.frames.iter().map(|frame| frame.1));
+        let construct_time = mean(self.construct_times.iter().copied());
+        let fps = if frame_time > 0.0 {
+            1.0 / frame_time
+        } else {
+            0.0
+        };
+        let lines = [
+            format!("FPS {:.1}", fps),
+            format!("FRAME {:.2} MS", frame_time * 1000.0),
+            format!("CONSTRUCT {:.2} MS", construct_time * 1000.0),
+            format!("RENDER {:.2} MS", render_time * 1000.0),
+            format!("QUADS {}", worker.instances),
+            format!(
+                "POOL {:.2} MB",
+                worker.pool_bytes as f64 / (1024.0 * 1024.0)
+            ),
+        ];
+
+        let mut builder = QuadBuilder {
+            size: (size.0 as f32, size.1 as f32),
+            quads: Vec::new(),
+        };
+        let graph_width = HISTORY as f32 * BAR_WIDTH;
+        let height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT + 3.0 * PADDING;
+        builder.rect(
+            MARGIN,
+            MARGIN,
+            graph_width + 2.0 * PADDING,
+            height,
+            BACKGROUND,
+        );
+
+        let left = MARGIN + PADDING;
+        for (i, line) in lines.iter().enumerate() {
+            builder.text(left, MARGIN + PADDING + i as f32 * LINE_HEIGHT, line);
+        }
+
+        // frame time bars with the part spent rendering drawn on top, newest on the right
+        let bottom = MARGIN + height - PADDING;
+        let offset = (HISTORY - self.frames.len()) as f32 * BAR_WIDTH;
+        for (i, &(frame_time, render_time)) in self.frames.iter().enumerate() {
+            let x = left + offset + i as f32 * BAR_WIDTH;
+            let frame_height = bar_height(frame_time);
+            let render_height = bar_height(render_time);
+            builder.rect(
+                x,
+                bottom - frame_height,
+                BAR_WIDTH,
+                frame_height,
+                frame_color(frame_time),
+            );
+            builder.rect(
+                x,
+                bottom - render_height,
+                BAR_WIDTH,
+                render_height,
+                RENDER_BAR,
+            );
+        }
+        // 60 FPS reference line
+        let target_height = bar_height(1.0 / 60.0);
+        builder.rect(left, bottom - target_height, graph_width, 1.0, TEXT);
+
+        builder.quads
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    match count {
+        0 => 0.0,
+        count => sum / count as f64,
+    }
+}
+
+/// Height of a graph bar for a duration in seconds.
+fn bar_height(time: f64) -> f32 {
+    (time / GRAPH_MAX).min(1.0) as f32 * GRAPH_HEIGHT
+}
+
+/// Collects axis aligned rectangles given in pixels from the top left corner.
+struct QuadBuilder {
+    size: (f32, f32),
+    quads: Vec<Quad>,
+}
+
+impl QuadBuilder {
+    fn rect(&mut self, x: f32, y: f32, w: f32, h: f32, color: Color) {
+        if w <= 0.0 || h <= 0.0 {
+            return;
+        }
+        let (width, height) = self.size;
+        self.quads.push(Quad::new(
+            (x + w * 0.5) / width * 2.0 - 1.0,
+            1.0 - (y + h * 0.5) / height * 2.0,
+            w / width * 2.0,
+            h / height * 2.0,
+            color,
+        ));
+    }
+
+    /// Draw a line of text, merging horizontal runs of font pixels into a single quad.
+    fn text(&mut self, x: f32, y: f32, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            let glyph_x = x + i as f32 * 4.0 * SCALE;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                let mut column = 0;
+                while column < 3 {
+                    if bits & (0b100 >> column) == 0 {
+                        column += 1;
+                        continue;
+                    }
+                    let start = column;
+                    while column < 3 && bits & (0b100 >> column) != 0 {
+                        column += 1;
+                    }
+                    self.rect(
+                        glyph_x + start as f32 * SCALE,
+                        y + row as f32 * SCALE,
+                        (column - start) as f32 * SCALE,
+                        SCALE,
+                        TEXT,
+                    );
+                }
+            }
+        }
+    }
+}
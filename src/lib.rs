@@ -1,8 +1,20 @@
 use pyo3::prelude::*;
 use winit::event_loop::{ControlFlow, EventLoop};
 
+mod adapter;
+mod animate;
 mod construct;
+mod cull;
+mod effects;
+mod error;
+mod hud;
+mod logging;
+mod path;
 mod render;
+mod scene;
+mod shader;
+mod timer;
+mod trace;
 mod ui;
 mod utils;
 mod window;
@@ -11,20 +23,425 @@ mod window;
 mod guilible {
     use super::*;
 
+    #[pymodule_export]
+    use error::AdapterError;
+    #[pymodule_export]
+    use error::DeviceError;
+    #[pymodule_export]
+    use error::GuilibleError;
+    #[pymodule_export]
+    use error::ShaderError;
+    #[pymodule_export]
+    use error::SurfaceError;
+    #[pymodule_export]
+    use error::UnsupportedSurfaceError;
+    #[pymodule_export]
+    use error::WindowError;
+    #[pymodule_export]
+    use error::WorkerError;
+
+    #[pymodule_init]
+    fn init(_m: &Bound<'_, PyModule>) -> PyResult<()> {
+        logging::init();
+        Ok(())
+    }
+
+    fn backends(names: Option<&str>) -> PyResult<wgpu::Backends> {
+        match names {
+            None => Ok(wgpu::Backends::PRIMARY),
+            Some(names) => adapter::parse_backends(names).ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(
+                    "backend must be a comma separated list of \"vulkan\", \"metal\", \"dx12\", \"gl\", \"primary\" or \"all\"",
+                )
+            }),
+        }
+    }
+
+    /// Describe the adapters available for the given backends, with their name, type and limits.
+    #[pyfunction]
+    #[pyo3(signature = (backend=None))]
+    fn adapters<'py>(
+        py: Python<'py>,
+        backend: Option<&str>,
+    ) -> PyResult<Vec<Bound<'py, pyo3::types::PyDict>>> {
+        let backends = backends(backend)?;
+        py.allow_threads(|| adapter::enumerate_adapters(backends))
+            .iter()
+            .map(|adapter| {
+                let info = adapter.get_info();
+                let limits = adapter.limits();
+                let device_type = match info.device_type {
+                    wgpu::DeviceType::IntegratedGpu => "integrated_gpu",
+                    wgpu::DeviceType::DiscreteGpu => "discrete_gpu",
+                    wgpu::DeviceType::VirtualGpu => "virtual_gpu",
+                    wgpu::DeviceType::Cpu => "cpu",
+                    wgpu::DeviceType::Other => "other",
+                };
+                let limits_dict = pyo3::types::PyDict::new(py);
+                limits_dict
+                    .set_item("max_texture_dimension_2d", limits.max_texture_dimension_2d)?;
+                limits_dict.set_item("max_buffer_size", limits.max_buffer_size)?;
+                limits_dict.set_item(
+                    "max_storage_buffer_binding_size",
+                    limits.max_storage_buffer_binding_size,
+                )?;
+                limits_dict.set_item(
+                    "max_uniform_buffer_binding_size",
+                    limits.max_uniform_buffer_binding_size,
+                )?;
+                limits_dict.set_item("max_bind_groups", limits.max_bind_groups)?;
+                limits_dict.set_item(
+                    "max_compute_workgroup_size_x",
+                    limits.max_compute_workgroup_size_x,
+                )?;
+
+                let dict = pyo3::types::PyDict::new(py);
+                dict.set_item("name", info.name)?;
+                dict.set_item("backend", info.backend.to_str())?;
+                dict.set_item("device_type", device_type)?;
+                dict.set_item("driver", info.driver)?;
+                dict.set_item("driver_info", info.driver_info)?;
+                dict.set_item("vendor", info.vendor)?;
+                dict.set_item("device", info.device)?;
+                dict.set_item("limits", limits_dict)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
     #[pyclass]
-    struct Window {}
+    struct Window {
+        title: String,
+        msaa: u32,
+        adapter: adapter::AdapterSettings,
+        shaders: shader::ShaderSettings,
+        hud: std::sync::Arc<std::sync::atomic::AtomicBool>,
+        stats: utils::SharedStats,
+        pool_budget: Option<u64>,
+        workers: construct::WorkerMode,
+        pacing: construct::Pacing,
+        changes: construct::SceneChanges,
+        on_device_lost: Option<std::sync::Arc<Py<PyAny>>>,
+        on_close: Option<std::sync::Arc<Py<PyAny>>>,
+    }
 
     #[pymethods]
     impl Window {
         #[new]
-        fn new() -> Self {
-            Window {}
+        #[allow(clippy::too_many_arguments)] // mirrors the Python keyword arguments
+        #[pyo3(signature = (
+            title="guilible".to_string(),
+            msaa=1,
+            shader_dir=None,
+            backend=None,
+            power_preference=None,
+            force_fallback_adapter=false,
+            adapter=None,
+            hud=false,
+            pool_budget=Some(window::DEFAULT_POOL_BUDGET),
+            workers="window",
+            pacing="mailbox",
+        ))]
+        fn new(
+            title: String,
+            msaa: u32,
+            shader_dir: Option<std::path::PathBuf>,
+            backend: Option<&str>,
+            power_preference: Option<&str>,
+            force_fallback_adapter: bool,
+            adapter: Option<String>,
+            hud: bool,
+            pool_budget: Option<u64>,
+            workers: &str,
+            pacing: &str,
+        ) -> PyResult<Self> {
+            if ![1, 2, 4, 8].contains(&msaa) {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "msaa must be one of 1, 2, 4 or 8",
+                ));
+            }
+            // an adapter picked by name leaves nothing to prefer
+            if power_preference.is_some() && adapter.is_some() {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "power_preference cannot be combined with adapter",
+                ));
+            }
+            let power_preference = adapter::parse_power_preference(
+                power_preference.unwrap_or("high"),
+            )
+            .ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(
+                    "power_preference must be one of \"high\", \"low\" or \"none\"",
+                )
+            })?;
+            let workers = construct::parse_worker_mode(workers).ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err("workers must be \"window\" or \"layer\"")
+            })?;
+            let pacing = construct::parse_pacing(pacing).ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(
+                    "pacing must be one of \"mailbox\", \"lockstep\" or \"reuse_last\"",
+                )
+            })?;
+            Ok(Window {
+                title,
+                msaa,
+                adapter: adapter::AdapterSettings {
+                    backends: backends(backend)?,
+                    power_preference,
+                    force_fallback_adapter,
+                    name: adapter,
+                },
+                shaders: shader::ShaderSettings {
+                    shader_dir,
+                    ..Default::default()
+                },
+                hud: std::sync::Arc::new(hud.into()),
+                stats: utils::SharedStats::default(),
+                pool_budget,
+                workers,
+                pacing,
+                changes: construct::SceneChanges::default(),
+                on_device_lost: None,
+                on_close: None,
+            })
         }
 
-        fn start(&mut self) {
-            let event_loop = EventLoop::new().unwrap();
-            event_loop.set_control_flow(ControlFlow::Wait);
-            let _ = event_loop.run_app(&mut window::Application::default());
+        /// Replace the quad shading of a layer with custom WGSL, `None` restores the default.
+        #[pyo3(signature = (layer, source))]
+        fn set_shader(&self, layer: &str, source: Option<&str>) -> PyResult<()> {
+            let shader = match source {
+                Some(source) => Some(std::sync::Arc::new(
+                    shader::CustomShader::new(source).map_err(error::Error::Shader)?,
+                )),
+                None => None,
+            };
+            let mut layer_shaders = self.shaders.layer_shaders.lock().unwrap();
+            layer_shaders.entry(layer.to_string()).or_default().shader = shader;
+            drop(layer_shaders);
+            self.changes.notify();
+            Ok(())
+        }
+
+        /// Set the `uniforms` array of a layer's custom shader from a flat list of up to 64 floats.
+        fn set_uniforms(&self, layer: &str, values: Vec<f32>) -> PyResult<()> {
+            let mut layer_shaders = self.shaders.layer_shaders.lock().unwrap();
+            if !layer_shaders
+                .entry(layer.to_string())
+                .or_default()
+                .set_uniforms(&values)
+            {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "at most 64 uniform values are supported",
+                ));
+            }
+            drop(layer_shaders);
+            self.changes.notify();
+            Ok(())
+        }
+
+        /// Show or hide the frame statistics overlay, it can also be toggled with F3.
+        fn show_hud(&self, visible: bool) {
+            self.hud.store(visible, std::sync::atomic::Ordering::SeqCst);
         }
+
+        /// Frame timing statistics collected since the window was created, in milliseconds.
+        ///
+        /// `frame` is the time between presented frames, `render` and `construct` the time the
+        /// render thread and the construction worker spend on a frame. `gpu_copy` and `gpu_render`
+        /// are the GPU time of the buffer uploads and render passes, they stay empty if the
+        /// adapter does not support timestamp queries. `pool` holds the counters of the buffer pool
+        /// of the construction worker. `latency` is the time from an input event until the first
+        /// frame constructed after it is presented.
+        fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+            let stats = self.stats.lock().unwrap();
+            let dict = pyo3::types::PyDict::new(py);
+            dict.set_item("frame", stats_dict(py, &stats.frame)?)?;
+            dict.set_item("render", stats_dict(py, &stats.render)?)?;
+            dict.set_item("construct", stats_dict(py, &stats.construct)?)?;
+            dict.set_item("gpu_copy", stats_dict(py, &stats.gpu_copy)?)?;
+            dict.set_item("gpu_render", stats_dict(py, &stats.gpu_render)?)?;
+            dict.set_item("latency", stats_dict(py, &stats.latency)?)?;
+            // all workers of the window combined
+            let pool_stats = construct::PoolStats::total(&stats.pools);
+            let pool = pyo3::types::PyDict::new(py);
+            pool.set_item("allocations", pool_stats.allocations)?;
+            pool.set_item("reuses", pool_stats.reuses)?;
+            pool.set_item("releases", pool_stats.releases)?;
+            pool.set_item("shrinks", pool_stats.shrinks)?;
+            pool.set_item("waits", pool_stats.waits)?;
+            pool.set_item("over_budget", pool_stats.over_budget)?;
+            pool.set_item("allocated_bytes", pool_stats.allocated_bytes)?;
+            pool.set_item("in_flight_bytes", pool_stats.in_flight_bytes)?;
+            pool.set_item("buffers", pool_stats.buffers)?;
+            pool.set_item("budget", pool_stats.budget)?;
+            dict.set_item("pool", pool)?;
+            Ok(dict)
+        }
+
+        /// Call `callback(reason)` when the GPU device is lost, before the renderer is recreated.
+        #[pyo3(signature = (callback))]
+        fn on_device_lost(&mut self, callback: Option<Py<PyAny>>) {
+            self.on_device_lost = callback.map(std::sync::Arc::new);
+        }
+
+        /// Call `callback()` when the user closes this window.
+        #[pyo3(signature = (callback))]
+        fn on_close(&mut self, callback: Option<Py<PyAny>>) {
+            self.on_close = callback.map(std::sync::Arc::new);
+        }
+
+        /// Open the window and block until it is closed, see `run` to open several windows.
+        ///
+        /// `trace` is the path of a Chrome Trace Event file written once the window is closed,
+        /// it can be opened in Perfetto or `chrome://tracing`.
+        #[pyo3(signature = (trace=None))]
+        fn start(
+            slf: PyRef<'_, Self>,
+            py: Python<'_>,
+            trace: Option<std::path::PathBuf>,
+        ) -> PyResult<()> {
+            let settings = vec![slf.settings()];
+            // callbacks may use the window while it is open
+            drop(slf);
+            run_windows(py, settings, trace)
+        }
+    }
+
+    impl Window {
+        fn settings(&self) -> window::Settings {
+            window::Settings {
+                title: self.title.clone(),
+                msaa_samples: self.msaa,
+                adapter: self.adapter.clone(),
+                shaders: self.shaders.clone(),
+                hud: self.hud.clone(),
+                stats: self.stats.clone(),
+                // set by `run_windows` for all windows of a trace
+                trace: trace::Tracer::default(),
+                pool_budget: self.pool_budget,
+                workers: self.workers,
+                pacing: self.pacing,
+                pending_input: construct::PendingInput::default(),
+                changes: self.changes.clone(),
+                on_device_lost: self.on_device_lost.clone().map(|callback| {
+                    Box::new(move |reason: &str| {
+                        Python::with_gil(|py| {
+                            callback
+                                .call1(py, (reason,))
+                                .map(drop)
+                                .map_err(error::Error::Callback)
+                        })
+                    }) as window::DeviceLostCallback
+                }),
+                on_close: self.on_close.clone().map(|callback| {
+                    Box::new(move || {
+                        Python::with_gil(|py| {
+                            callback.call0(py).map(drop).map_err(error::Error::Callback)
+                        })
+                    }) as window::CloseCallback
+                }),
+            }
+        }
+    }
+
+    fn stats_dict<'py>(
+        py: Python<'py>,
+        stats: &utils::Stats,
+    ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let ms = |seconds: f64| seconds * 1000.0;
+        let [p50, p95, p99] = stats.percentiles();
+        let dict = pyo3::types::PyDict::new(py);
+        dict.set_item("count", stats.count())?;
+        dict.set_item("mean_ms", ms(stats.mean()))?;
+        dict.set_item("std_ms", ms(stats.std_dev()))?;
+        dict.set_item("p50_ms", ms(p50))?;
+        dict.set_item("p95_ms", ms(p95))?;
+        dict.set_item("p99_ms", ms(p99))?;
+        dict.set_item("max_ms", ms(stats.max()))?;
+        dict.set_item("dropped", stats.dropped)?;
+        // (upper bound in milliseconds, count) over the most recent frames
+        let histogram: Vec<(f64, u64)> = utils::HISTOGRAM_BOUNDS
+            .into_iter()
+            .map(ms)
+            .zip(stats.histogram())
+            .collect();
+        dict.set_item("histogram", histogram)?;
+        Ok(dict)
+    }
+
+    /// Open all windows on one GPU device and block until the last one is closed, optionally
+    /// writing a trace of all windows like `Window.start`.
+    #[pyfunction]
+    #[pyo3(signature = (*windows, trace=None))]
+    fn run(
+        py: Python<'_>,
+        windows: Vec<PyRef<'_, Window>>,
+        trace: Option<std::path::PathBuf>,
+    ) -> PyResult<()> {
+        if windows.is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "at least one window is required",
+            ));
+        }
+        // the windows share the device created for the first one
+        if windows[1..]
+            .iter()
+            .any(|window| window.adapter != windows[0].adapter)
+        {
+            return Err(error::GuilibleError::new_err(
+                "all windows have to use the same backend, power_preference, force_fallback_adapter and adapter",
+            ));
+        }
+        let settings = windows.iter().map(|window| window.settings()).collect();
+        // callbacks may use the windows while they are open
+        drop(windows);
+        run_windows(py, settings, trace)
+    }
+
+    fn run_windows(
+        py: Python<'_>,
+        mut settings: Vec<window::Settings>,
+        trace: Option<std::path::PathBuf>,
+    ) -> PyResult<()> {
+        // create the file right away so that a bad path fails before the windows open
+        let trace_file = match trace {
+            Some(path) => Some(std::fs::File::create(path).map_err(error::Error::Trace)?),
+            None => None,
+        };
+        let tracer = match trace_file {
+            Some(_) => trace::Tracer::new(),
+            None => trace::Tracer::default(),
+        };
+        for settings in settings.iter_mut() {
+            settings.trace = tracer.clone();
+        }
+
+        // release the GIL while the windows are open, the worker threads need it for logging
+        let result = py.allow_threads(move || -> error::Result<()> {
+            let event_loop = EventLoop::with_user_event()
+                .build()
+                .map_err(error::Error::EventLoop)?;
+            event_loop.set_control_flow(ControlFlow::Wait);
+            let mut application = window::Application::new(settings, event_loop.create_proxy());
+            let result = event_loop
+                .run_app(&mut application)
+                .map_err(error::Error::EventLoop)
+                // errors inside the event loop close the windows and are raised once it has exited
+                .and_then(|()| match application.take_error() {
+                    Some(error) => Err(error),
+                    None => Ok(()),
+                });
+
+            // the trace is written even if the windows closed with an error, to help finding it
+            match trace_file {
+                Some(file) => result.and(
+                    tracer
+                        .write(std::io::BufWriter::new(file))
+                        .map_err(error::Error::Trace),
+                ),
+                None => result,
+            }
+        });
+        Ok(result?)
     }
 }
@@ -0,0 +1,54 @@
+use pyo3::prelude::*;
+
+/// Forwards `log` records to Python's `logging` module, so that host applications can filter,
+/// silence or capture them like any other Python logger. Records of the crate end up in the
+/// `guilible.<module>` loggers, records of dependencies (wgpu, naga, ...) under their own name.
+struct PythonLogger;
+
+impl PythonLogger {
+    /// Python logging level of a record, `TRACE` maps to 5 as there is no predefined level for it.
+    fn python_level(level: log::Level) -> u32 {
+        match level {
+            log::Level::Error => 40,
+            log::Level::Warn => 30,
+            log::Level::Info => 20,
+            log::Level::Debug => 10,
+            log::Level::Trace => 5,
+        }
+    }
+}
+
+impl log::Log for PythonLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // dependencies are very chatty below warnings, and every record has to take the GIL
+        metadata.target().starts_with("guilible") || metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let name = record.target().replace("::", ".");
+        let level = Self::python_level(record.level());
+        let message = record.args().to_string();
+        Python::with_gil(|py| {
+            let result = py
+                .import("logging")
+                .and_then(|logging| logging.call_method1("getLogger", (name,)))
+                .and_then(|logger| logger.call_method1("log", (level, message)));
+            // there is nowhere left to report a failing logger to
+            if let Err(e) = result {
+                e.print(py);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the Python logging bridge, does nothing if another logger has already been set.
+pub fn init() {
+    if log::set_logger(&PythonLogger).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
@@ -0,0 +1,29 @@
+struct VertexIn {
+    @location(0) position: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// view transform of the layer
+struct Camera {
+    transform: vec4<f32>,
+    translation: vec2<f32>,
+};
+
+struct VertexOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    let camera_transform = mat2x2<f32>(camera.transform.xy, camera.transform.zw);
+    let pos = camera_transform * in.position + camera.translation;
+    return VertexOut(vec4<f32>(pos, 0.0, 1.0), in.color);
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,209 @@
+use crate::render;
+use crate::utils;
+use lyon::math::{point, vector, Angle, Point};
+use lyon::tessellation::{
+    BuffersBuilder, FillOptions, FillTessellator, FillVertex, StrokeOptions, StrokeTessellator,
+    StrokeVertex, VertexBuffers,
+};
+
+/// Maximum distance between the flattened and the exact curves, in normalized device coordinates.
+const TOLERANCE: f32 = 0.0005;
+
+#[derive(Clone, Copy)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+#[derive(Clone)]
+pub struct Fill {
+    pub color: utils::Color,
+    pub rule: FillRule,
+}
+
+#[derive(Clone)]
+pub struct Stroke {
+    pub color: utils::Color,
+    pub width: f32,
+}
+
+#[derive(Clone)]
+pub struct PathStyle {
+    pub fill: Option<Fill>,
+    pub stroke: Option<Stroke>,
+}
+
+impl PathStyle {
+    /// Copy of the style with all colors faded by `opacity`.
+    pub fn with_opacity(&self, opacity: f32) -> PathStyle {
+        let fade = |color: utils::Color| utils::Color {
+            a: color.a * opacity,
+            ..color
+        };
+        PathStyle {
+            fill: self.fill.as_ref().map(|fill| Fill {
+                color: fade(fill.color),
+                ..fill.clone()
+            }),
+            stroke: self.stroke.as_ref().map(|stroke| Stroke {
+                color: fade(stroke.color),
+                ..stroke.clone()
+            }),
+        }
+    }
+}
+
+/// Builds a path out of straight lines, curves and arcs.
+pub struct PathBuilder {
+    builder: lyon::path::path::Builder,
+    current: Option<Point>,
+}
+
+impl PathBuilder {
+    pub fn new() -> Self {
+        PathBuilder {
+            builder: lyon::path::Path::builder(),
+            current: None,
+        }
+    }
+
+    /// Start a new sub-path, leaving the previous one open.
+    pub fn move_to(&mut self, x: f32, y: f32) -> &mut Self {
+        if self.current.is_some() {
+            self.builder.end(false);
+        }
+        self.builder.begin(point(x, y));
+        self.current = Some(point(x, y));
+        self
+    }
+
+    pub fn line_to(&mut self, x: f32, y: f32) -> &mut Self {
+        self.ensure_started(point(x, y));
+        self.builder.line_to(point(x, y));
+        self.current = Some(point(x, y));
+        self
+    }
+
+    pub fn quadratic_to(&mut self, cx: f32, cy: f32, x: f32, y: f32) -> &mut Self {
+        self.ensure_started(point(cx, cy));
+        self.builder.quadratic_bezier_to(point(cx, cy), point(x, y));
+        self.current = Some(point(x, y));
+        self
+    }
+
+    pub fn cubic_to(
+        &mut self,
+        c1x: f32,
+        c1y: f32,
+        c2x: f32,
+        c2y: f32,
+        x: f32,
+        y: f32,
+    ) -> &mut Self {
+        self.ensure_started(point(c1x, c1y));
+        self.builder
+            .cubic_bezier_to(point(c1x, c1y), point(c2x, c2y), point(x, y));
+        self.current = Some(point(x, y));
+        self
+    }
+
+    /// Elliptic arc around a center, connected to the current point with a straight line.
+    /// Angles are given in radians, counter-clockwise starting from the positive x-axis.
+    pub fn arc(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+        start_angle: f32,
+        sweep_angle: f32,
+    ) -> &mut Self {
+        let arc = lyon::geom::Arc {
+            center: point(cx, cy),
+            radii: vector(rx, ry),
+            start_angle: Angle::radians(start_angle),
+            sweep_angle: Angle::radians(sweep_angle),
+            x_rotation: Angle::zero(),
+        };
+        let from = arc.from();
+        match self.current {
+            Some(_) => {
+                self.builder.line_to(from);
+            }
+            None => {
+                self.builder.begin(from);
+            }
+        }
+        arc.for_each_quadratic_bezier(&mut |curve| {
+            self.builder.quadratic_bezier_to(curve.ctrl, curve.to);
+        });
+        self.current = Some(arc.to());
+        self
+    }
+
+    /// Close the current sub-path with a straight line back to its start.
+    pub fn close(&mut self) -> &mut Self {
+        if self.current.take().is_some() {
+            self.builder.end(true);
+        }
+        self
+    }
+
+    pub fn build(mut self) -> lyon::path::Path {
+        if self.current.is_some() {
+            self.builder.end(false);
+        }
+        self.builder.build()
+    }
+
+    fn ensure_started(&mut self, at: Point) {
+        if self.current.is_none() {
+            self.builder.begin(at);
+            self.current = Some(at);
+        }
+    }
+}
+
+/// Triangle mesh produced by tessellating paths, ready to be uploaded to the GPU.
+pub type Mesh = VertexBuffers<render::MeshVertex, u32>;
+
+/// Tessellate the fill and stroke of a path into triangles, appending them to `mesh`.
+pub fn tessellate(path: &lyon::path::Path, style: &PathStyle, mesh: &mut Mesh) {
+    if let Some(fill) = &style.fill {
+        let options = FillOptions::tolerance(TOLERANCE).with_fill_rule(match fill.rule {
+            FillRule::NonZero => lyon::tessellation::FillRule::NonZero,
+            FillRule::EvenOdd => lyon::tessellation::FillRule::EvenOdd,
+        });
+        let result = FillTessellator::new().tessellate_path(
+            path,
+            &options,
+            &mut BuffersBuilder::new(mesh, |vertex: FillVertex| render::MeshVertex {
+                x: vertex.position().x,
+                y: vertex.position().y,
+                color: fill.color,
+            }),
+        );
+        if let Err(e) = result {
+            log::warn!("failed to tessellate path fill: {:?}", e);
+        }
+    }
+
+    if let Some(stroke) = &style.stroke {
+        let options = StrokeOptions::tolerance(TOLERANCE)
+            .with_line_width(stroke.width)
+            .with_line_join(lyon::tessellation::LineJoin::Round)
+            .with_line_cap(lyon::tessellation::LineCap::Round);
+        let result = StrokeTessellator::new().tessellate_path(
+            path,
+            &options,
+            &mut BuffersBuilder::new(mesh, |vertex: StrokeVertex| render::MeshVertex {
+                x: vertex.position().x,
+                y: vertex.position().y,
+                color: stroke.color,
+            }),
+        );
+        if let Err(e) = result {
+            log::warn!("failed to tessellate path stroke: {:?}", e);
+        }
+    }
+}
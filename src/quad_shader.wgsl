@@ -2,32 +2,149 @@ struct Quad {
     center: vec2<f32>,
     size: vec2<f32>,
     color: vec4<f32>,
+    // column-major 2x2 matrix applied around the center
+    transform: vec4<f32>,
+    corner_radius: f32,
+    blur: f32,
+    flags: u32,
+    _padding: u32,
+};
+
+// view transform of the layer
+struct Camera {
+    transform: vec4<f32>,
+    translation: vec2<f32>,
 };
 
 struct VertexOut {
     @builtin(position) pos: vec4<f32>,
     @location(0) color: vec4<f32>,
+    @location(1) local: vec2<f32>,
+    @location(2) @interpolate(flat) half_size: vec2<f32>,
+    @location(3) @interpolate(flat) corner_radius: f32,
+    @location(4) @interpolate(flat) sigma: f32,
+    @location(5) @interpolate(flat) flags: u32,
 };
 
+const FLAG_BACKDROP_BLUR: u32 = 1u;
+
 @group(0) @binding(0) var<storage, read> quad_buffer: array<Quad>;
 
+@group(1) @binding(0) var<uniform> camera: Camera;
+// values set from Python, only used by custom shaders
+@group(1) @binding(1) var<uniform> uniforms: array<vec4<f32>, 16>;
+
+@group(2) @binding(0) var backdrop_texture: texture_2d<f32>;
+@group(2) @binding(1) var backdrop_sampler: sampler;
+
 @vertex
 fn vs_main(@builtin(vertex_index) vertex_index: u32, @builtin(instance_index) instance_index: u32) -> VertexOut {
     let quad = quad_buffer[instance_index];
 
-    // calculate the position of the vertex
-    let x = quad.center.x + quad.size.x * select(-0.5, 0.5, vertex_index / 2u == 0u);
-    let y = quad.center.y + quad.size.y * select(-0.5, 0.5, vertex_index % 2u == 0u);
+    // blurred quads (shadows) are expanded by three standard deviations to fit the falloff
+    let half_size = quad.size * 0.5;
+    let sigma = quad.blur * 0.5;
+    let extent = half_size + vec2<f32>(sigma * 3.0);
+
+    // calculate the position of the vertex relative to the untransformed quad center
+    let local = vec2<f32>(
+        extent.x * select(-1.0, 1.0, vertex_index / 2u == 0u),
+        extent.y * select(-1.0, 1.0, vertex_index % 2u == 0u),
+    );
+    let transform = mat2x2<f32>(quad.transform.xy, quad.transform.zw);
+    let pos = quad.center + transform * local;
+    let camera_transform = mat2x2<f32>(camera.transform.xy, camera.transform.zw);
 
-    // return the vertex position and color
+    // return the vertex position and the parameters needed for shading
     return VertexOut(
-        vec4<f32>(x, y, 0.0, 1.0),
-        quad.color
+        vec4<f32>(camera_transform * pos + camera.translation, 0.0, 1.0),
+        quad.color,
+        local,
+        half_size,
+        min(quad.corner_radius, min(half_size.x, half_size.y)),
+        sigma,
+        quad.flags,
     );
 }
 
+// signed distance to a rounded box centered at the origin
+fn sd_rounded_box(p: vec2<f32>, half_size: vec2<f32>, radius: f32) -> f32 {
+    let q = abs(p) - half_size + radius;
+    return length(max(q, vec2<f32>(0.0))) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    let pi = 3.141592653589793;
+    return exp(-(x * x) / (2.0 * sigma * sigma)) / (sqrt(2.0 * pi) * sigma);
+}
+
+// approximation of the error function, accurate enough for shading
+fn erf(x: vec2<f32>) -> vec2<f32> {
+    let s = sign(x);
+    let a = abs(x);
+    var y = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    y = y * y;
+    return s - s / (y * y);
+}
+
+// closed-form blur of a rounded box along x for a fixed row y
+fn rounded_box_shadow_x(x: f32, y: f32, sigma: f32, radius: f32, half_size: vec2<f32>) -> f32 {
+    let delta = min(half_size.y - radius - abs(y), 0.0);
+    let curved = half_size.x - radius + sqrt(max(0.0, radius * radius - delta * delta));
+    let integral = 0.5 + 0.5 * erf((x + vec2<f32>(-curved, curved)) * (sqrt(0.5) / sigma));
+    return integral.y - integral.x;
+}
+
+// gaussian-blurred rounded box, integrating the closed-form x blur over a few samples along y
+fn rounded_box_shadow(p: vec2<f32>, half_size: vec2<f32>, radius: f32, sigma: f32) -> f32 {
+    let low = p.y - half_size.y;
+    let high = p.y + half_size.y;
+    let start = clamp(-3.0 * sigma, low, high);
+    let end = clamp(3.0 * sigma, low, high);
+
+    let step = (end - start) / 4.0;
+    var y = start + step * 0.5;
+    var value = 0.0;
+    for (var i = 0; i < 4; i++) {
+        value += rounded_box_shadow_x(p.x, p.y - y, sigma, radius, half_size) * gaussian(y, sigma) * step;
+        y += step;
+    }
+    return value;
+}
+
+fn coverage(in: VertexOut, pixel_size: f32) -> f32 {
+    if in.sigma > 0.0 {
+        return rounded_box_shadow(in.local, in.half_size, in.corner_radius, in.sigma);
+    }
+    if in.corner_radius > 0.0 {
+        let distance = sd_rounded_box(in.local, in.half_size, in.corner_radius);
+        return clamp(0.5 - distance / pixel_size, 0.0, 1.0);
+    }
+    return 1.0;
+}
 
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    return in.color;
-}
\ No newline at end of file
+    // derivatives have to be taken in uniform control flow
+    let pixel_size = max(fwidth(in.local).x, fwidth(in.local).y);
+
+    // backdrop panels are drawn separately on top of the blurred scene
+    if (in.flags & FLAG_BACKDROP_BLUR) != 0u {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage(in, pixel_size));
+}
+
+@fragment
+fn fs_backdrop(in: VertexOut) -> @location(0) vec4<f32> {
+    let pixel_size = max(fwidth(in.local).x, fwidth(in.local).y);
+
+    if (in.flags & FLAG_BACKDROP_BLUR) == 0u {
+        discard;
+    }
+
+    // tint the blurred scene behind the panel with the panel color
+    let uv = in.pos.xy / vec2<f32>(textureDimensions(backdrop_texture));
+    let backdrop = textureSampleLevel(backdrop_texture, backdrop_sampler, uv, 0.0);
+    return vec4<f32>(mix(backdrop.rgb, in.color.rgb, in.color.a), coverage(in, pixel_size));
+}
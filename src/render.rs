@@ -1,65 +1,974 @@
-use std::sync::mpsc;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 
+use crate::animate;
 use crate::construct;
+use crate::cull;
+use crate::effects;
+use crate::error::{Error, Result};
+use crate::hud;
+use crate::shader;
+use crate::timer;
+use crate::trace;
 use crate::utils;
+use crate::window;
 use bytemuck::NoUninit;
+use pollster::FutureExt;
 use wgpu::include_wgsl;
+use wgpu::util::DeviceExt;
+
+/// Quad flag marking translucent panels that show a blurred version of the scene behind them.
+pub const QUAD_FLAG_BACKDROP_BLUR: u32 = 1;
+
+/// Color attachment of a render pass, resolved into `resolve_target` when multisampled.
+pub struct RenderTarget<'a> {
+    pub view: &'a wgpu::TextureView,
+    pub resolve_target: Option<&'a wgpu::TextureView>,
+}
+
+/// How the contents of a layer are combined with the layers below it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Alpha,
+    Additive,
+    /// overwrites the layers below, transparency and anti-aliased edges are ignored
+    Opaque,
+}
+
+impl BlendMode {
+    const ALL: [BlendMode; 3] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Opaque];
+
+    fn color_target(self, format: wgpu::TextureFormat) -> wgpu::ColorTargetState {
+        wgpu::ColorTargetState {
+            format,
+            blend: Some(self.blend_state()),
+            write_mask: wgpu::ColorWrites::ALL,
+        }
+    }
+
+    fn blend_state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            BlendMode::Opaque => wgpu::BlendState::REPLACE,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+struct CameraUniform {
+    transform: [f32; 4],
+    translation: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl From<utils::Transform> for CameraUniform {
+    fn from(transform: utils::Transform) -> CameraUniform {
+        CameraUniform {
+            transform: transform.linear(),
+            translation: [transform.tx, transform.ty],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Last uploaded contents of a layer, kept until the construction worker sends a newer version.
+struct RenderLayer {
+    blend: BlendMode,
+    camera: utils::Transform,
+    camera_buffer: wgpu::Buffer,
+    uniforms: shader::Uniforms,
+    uniform_buffer: wgpu::Buffer,
+    /// camera and user uniforms
+    layer_bind_group: wgpu::BindGroup,
+    custom_pipeline: Option<CustomPipeline>,
+    buffers: Option<construct::LayerBuffers>,
+    /// quads moved by their animations, drawn and culled instead of `buffers.quads`
+    animated: Option<animate::AnimatedQuads>,
+    /// visible quads of large layers, drawn instead of `buffers.quads`
+    culled: Option<cull::CulledQuads>,
+}
+
+/// Pipeline built from the custom shader of a layer, `None` if the shader failed to compile.
+struct CustomPipeline {
+    shader: Arc<shader::CustomShader>,
+    blend: BlendMode,
+    /// `QuadPipeline::generation` of the built-in shader it was compiled with
+    generation: u32,
+    pipeline: Option<wgpu::RenderPipeline>,
+}
+
+impl CustomPipeline {
+    /// Compile a custom shader, a failing shader is logged and the layer falls back to the default.
+    fn new(
+        quad_pipeline: &QuadPipeline,
+        shader: Arc<shader::CustomShader>,
+        blend: BlendMode,
+    ) -> CustomPipeline {
+        let pipeline = quad_pipeline.create_custom(&shader, blend);
+        if let Err(e) = &pipeline {
+            log::error!("{}", e);
+        }
+        CustomPipeline {
+            shader,
+            blend,
+            generation: quad_pipeline.generation,
+            pipeline: pipeline.ok(),
+        }
+    }
+}
+
+impl RenderLayer {
+    fn new(
+        device_arc: &Arc<wgpu::Device>,
+        layer_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let camera = utils::Transform::IDENTITY;
+        let camera_buffer = device_arc.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("layer camera buffer"),
+            contents: bytemuck::bytes_of(&CameraUniform::from(camera)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniforms = shader::Uniforms::default();
+        let uniform_buffer = device_arc.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("layer uniform buffer"),
+            contents: bytemuck::cast_slice(&uniforms),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layer_bind_group = device_arc.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("layer bind group"),
+            layout: layer_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        RenderLayer {
+            blend: BlendMode::Alpha,
+            camera,
+            camera_buffer,
+            uniforms,
+            uniform_buffer,
+            layer_bind_group,
+            custom_pipeline: None,
+            buffers: None,
+            animated: None,
+            culled: None,
+        }
+    }
+
+    fn has_backdrop_blur(&self) -> bool {
+        self.buffers
+            .as_ref()
+            .is_some_and(|buffers| buffers.backdrop_blur)
+    }
+
+    /// Culled quads to draw, `None` if all quads are drawn. Custom vertex shaders may move quads
+    /// into view, so their layers are never culled.
+    fn culled(&self) -> Option<&cull::CulledQuads> {
+        match &self.custom_pipeline {
+            Some(custom) if custom.shader.has_custom_vertex() => None,
+            _ => self.culled.as_ref(),
+        }
+    }
+
+    /// Runs of consecutive draw ranges that do or do not hold backdrop panels, as ranges of indices.
+    fn runs(&self) -> Vec<(bool, std::ops::Range<usize>)> {
+        let mut runs: Vec<(bool, std::ops::Range<usize>)> = Vec::new();
+        let draw_ranges = self.buffers.iter().flat_map(|b| b.draw_ranges.iter());
+        for (i, range) in draw_ranges.enumerate() {
+            match runs.last_mut() {
+                Some((backdrop, run)) if *backdrop == range.backdrop => run.end = i + 1,
+                _ => runs.push((range.backdrop, i..i + 1)),
+            }
+        }
+        runs
+    }
+
+    /// Clip rectangle of a draw range as seen through the layer's camera.
+    fn clip(&self, range: &DrawRange) -> Option<utils::ClipRect> {
+        match self.camera {
+            utils::Transform::IDENTITY => range.clip,
+            camera => range.clip.map(|clip| clip.transformed(&camera)),
+        }
+    }
+}
+
+/// Part of a layer drawn into the offscreen scene of the backdrop blur.
+enum SceneDraw<'a> {
+    /// draw ranges of the layer by index
+    Quads(&'a RenderLayer, std::ops::Range<usize>),
+    Panels(&'a RenderLayer, std::ops::Range<usize>),
+    Paths(&'a RenderLayer),
+}
+
+/// Pipelines shared by all windows with the same output format, sample count and shader source.
+pub struct Pipelines {
+    texture_out_format: wgpu::TextureFormat,
+    sample_count: u32,
+    shader_dir: Option<std::path::PathBuf>,
+    layer_bind_group_layout: wgpu::BindGroupLayout,
+    quad: QuadPipeline,
+    mesh: MeshPipeline,
+    animate: animate::AnimatePipeline,
+    /// `None` if the device does not support indirect drawing with a first instance
+    cull: Option<cull::CullPipeline>,
+}
+
+impl Pipelines {
+    pub fn new(
+        device_arc: Arc<wgpu::Device>,
+        texture_out_format: wgpu::TextureFormat,
+        sample_count: u32,
+        shader_dir: Option<std::path::PathBuf>,
+    ) -> Pipelines {
+        log::debug!("initializing render pipeline");
+
+        let layer_bind_group_layout =
+            device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("layer bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let quad_pipeline = QuadPipeline::new(
+            device_arc.clone(),
+            texture_out_format,
+            sample_count,
+            &layer_bind_group_layout,
+            shader_dir
+                .as_ref()
+                .map(|dir| shader::ShaderFile::new(dir.join("quad_shader.wgsl"))),
+        );
+        let mesh_pipeline = MeshPipeline::new(
+            device_arc.clone(),
+            texture_out_format,
+            sample_count,
+            &layer_bind_group_layout,
+        );
+        let animate_pipeline = animate::AnimatePipeline::new(device_arc.clone());
+        let cull_pipeline = cull::CullPipeline::new(device_arc.clone());
+
+        Pipelines {
+            texture_out_format,
+            sample_count,
+            shader_dir,
+            layer_bind_group_layout,
+            quad: quad_pipeline,
+            mesh: mesh_pipeline,
+            animate: animate_pipeline,
+            cull: cull_pipeline,
+        }
+    }
+
+    pub fn matches(
+        &self,
+        texture_out_format: wgpu::TextureFormat,
+        sample_count: u32,
+        shader_dir: Option<&std::path::PathBuf>,
+    ) -> bool {
+        self.texture_out_format == texture_out_format
+            && self.sample_count == sample_count
+            && self.shader_dir.as_ref() == shader_dir
+    }
+}
+
+/// Construction worker of some of the layers of a window, with the layers built from its messages.
+struct WorkerLayers {
+    worker: construct::ConstructionWorker,
+    layers: Vec<RenderLayer>,
+    stats: construct::WorkerStats,
+    /// time the animations of the layers are evaluated from
+    start_time: std::time::Instant,
+}
 
 pub struct Renderer {
-    quad_pipeline: QuadPipeline,
-    construction_worker: construct::ConstructionWorker,
+    device_arc: Arc<wgpu::Device>,
+    queue_arc: Arc<wgpu::Queue>,
+    pipelines: Rc<RefCell<Pipelines>>,
+    size: (u32, u32),
+    backdrop_blur: Option<effects::BackdropBlur>,
+    /// workers in the order their layers are drawn
+    workers: Vec<WorkerLayers>,
+    /// earliest input reflected by the frame being rendered
+    input_time: Option<std::time::Instant>,
+    hud: hud::Hud,
+    /// statistics overlay, created when it is first shown
+    hud_layer: Option<RenderLayer>,
+    stats: utils::SharedStats,
+    /// GPU time of the render passes, if the device supports timestamp queries
+    gpu_timer: Option<timer::GpuTimer>,
+    trace: trace::Tracer,
 }
 
 impl Renderer {
     pub fn new(
         device_arc: Arc<wgpu::Device>,
         queue_arc: Arc<wgpu::Queue>,
-        texture_out_format: wgpu::TextureFormat,
-    ) -> Renderer {
-        println!("├─ initializing render pipeline");
+        pipelines: Rc<RefCell<Pipelines>>,
+        size: (u32, u32),
+        settings: &window::Settings,
+        scenes: &mut Vec<construct::WorkerScene>,
+        wake: construct::Waker,
+    ) -> Result<Renderer> {
+        // continue with the scenes of a previous renderer or set up new ones
+        if scenes.is_empty() {
+            *scenes = construct::WorkerScene::setup(settings.workers);
+        }
+        let mut workers = scenes
+            .iter()
+            .enumerate()
+            .map(|(index, scene)| {
+                let name = format!("{}: {}", settings.title, scene.layer_names().join(", "));
+                let start_time = scene.start_time();
+                let worker = construct::create_construction_worker(
+                    construct::ConstructionWorkerDescriptor {
+                        device_arc: device_arc.clone(),
+                        queue_arc: queue_arc.clone(),
+                        bind_group_layout: pipelines.borrow().quad.bind_group_layout.clone(),
+                        name,
+                        index,
+                        layer_shaders: settings.shaders.layer_shaders.clone(),
+                        wake: wake.clone(),
+                        pacing: settings.pacing,
+                        pending_input: settings.pending_input.clone(),
+                        changes: settings.changes.clone(),
+                        stats: settings.stats.clone(),
+                        trace: settings.trace.clone(),
+                        pool_budget: settings.pool_budget,
+                    },
+                )?;
+                Ok(WorkerLayers {
+                    worker,
+                    layers: Vec::new(),
+                    stats: construct::WorkerStats::default(),
+                    start_time,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // the scenes are only handed over once all workers are running, so that they stay with
+        // the window if one of them fails to start
+        for (slot, scene) in workers.iter_mut().zip(std::mem::take(scenes)) {
+            slot.worker.start(scene);
+        }
+        let gpu_timer = timer::GpuTimer::new(device_arc.clone(), &queue_arc, "render timestamps");
 
-        let quad_pipeline = QuadPipeline::new(device_arc.clone(), texture_out_format);
+        Ok(Renderer {
+            device_arc,
+            queue_arc,
+            pipelines,
+            size,
+            backdrop_blur: None,
+            workers,
+            input_time: None,
+            hud: hud::Hud::default(),
+            hud_layer: None,
+            stats: settings.stats.clone(),
+            gpu_timer,
+            trace: settings.trace.clone(),
+        })
+    }
 
-        let construction_worker =
-            construct::create_construction_worker(construct::ConstructionWorkerDescriptor {
-                device_arc,
-                queue_arc,
-                bind_group_layout: quad_pipeline.bind_group_layout.clone(),
-            });
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+    }
+
+    /// Record the timing of the last frame for the statistics overlay.
+    pub fn record_frame(&mut self, frame_time: f64, render_time: f64) {
+        self.hud.record_frame(frame_time, render_time);
+    }
+
+    /// Read back the GPU time of earlier frames, to be called once the frame has been submitted.
+    pub fn frame_submitted(&mut self) {
+        let Some(gpu_timer) = &mut self.gpu_timer else {
+            return;
+        };
+        gpu_timer.map();
+        let durations = gpu_timer.read();
+        if !durations.is_empty() {
+            let mut stats = self.stats.lock().unwrap();
+            for duration in durations {
+                stats.gpu_render.update(duration);
+            }
+        }
+    }
 
-        Renderer {
-            quad_pipeline,
-            construction_worker,
+    /// Record the input-to-photon latency of the frame once it has been presented.
+    pub fn frame_presented(&mut self) {
+        if let Some(input_time) = self.input_time.take() {
+            let latency = input_time.elapsed().as_secs_f64();
+            self.stats.lock().unwrap().latency.update(latency);
         }
     }
 
     pub fn render(
         &mut self,
-        render_pass: &mut wgpu::RenderPass,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &RenderTarget,
+        show_hud: bool,
+    ) -> Result<Vec<construct::StorageBuffer>> {
+        let messages = {
+            let _span = self.trace.span("receive");
+            let messages = self.receive()?;
+            for (_, message) in messages.iter() {
+                self.trace.flow_end("frame", message.flow_id);
+            }
+            self.input_time = messages
+                .iter()
+                .filter_map(|(_, message)| message.input_time)
+                .min();
+            messages
+        };
+        self.pipelines.borrow_mut().quad.reload();
+        let pipelines = self.pipelines.clone();
+        let pipelines = pipelines.borrow();
+        self.update_custom_pipelines(&pipelines.quad);
+        let mut recycled = Vec::new();
+        if !messages.is_empty() {
+            let _span = self.trace.span("update layers");
+            for (index, message) in messages {
+                let mut layers = std::mem::take(&mut self.workers[index].layers);
+                self.workers[index].stats = message.stats;
+                recycled.extend(self.update_layers(&mut layers, message, &pipelines));
+                self.workers[index].layers = layers;
+            }
+            self.hud.record_worker(&self.worker_stats());
+        }
+        if show_hud {
+            let _span = self.trace.span("update hud");
+            self.update_hud(&pipelines);
+        }
+        self.animate_layers(encoder, &pipelines.animate);
+        if let Some(cull) = &pipelines.cull {
+            self.cull_layers(encoder, cull);
+        }
+
+        // layers up to the last one containing blurred panels are drawn offscreen, where each run
+        // of panels shows a blurred copy of what was drawn before it
+        let layers: Vec<&RenderLayer> = self
+            .workers
+            .iter()
+            .flat_map(|worker| worker.layers.iter())
+            .collect();
+        let num_blurred = layers
+            .iter()
+            .rposition(|layer| layer.has_backdrop_blur())
+            .map_or(0, |i| i + 1);
+        let mut render_pass = if num_blurred > 0 {
+            // the backdrop blur stage is only created once a frame contains blurred panels
+            let backdrop_blur = self.backdrop_blur.get_or_insert_with(|| {
+                log::debug!("initializing backdrop blur");
+                effects::BackdropBlur::new(
+                    self.device_arc.clone(),
+                    pipelines.texture_out_format,
+                    pipelines.sample_count,
+                    pipelines.quad.backdrop_bind_group_layout.clone(),
+                    effects::BlurSettings::default(),
+                )
+            });
+            backdrop_blur.prepare(self.size.0, self.size.1);
+
+            // keep the paint order, the scene is blurred again before every run of panels
+            let mut segments = vec![Vec::new()];
+            for layer in layers[..num_blurred].iter().copied() {
+                for (backdrop, ranges) in layer.runs() {
+                    let draw = match backdrop {
+                        true => {
+                            segments.push(Vec::new());
+                            SceneDraw::Panels(layer, ranges)
+                        }
+                        false => SceneDraw::Quads(layer, ranges),
+                    };
+                    segments.last_mut().unwrap().push(draw);
+                }
+                // paths are drawn on top of all quads of the layer, including backdrop panels
+                segments.last_mut().unwrap().push(SceneDraw::Paths(layer));
+            }
+            let num_segments = segments.len();
+            for (i, segment) in segments.into_iter().enumerate() {
+                if i > 0 {
+                    let _span = self.trace.span("blur");
+                    backdrop_blur.blur(encoder);
+                }
+                let _span = self.trace.span("scene pass");
+                let timestamp_writes = self
+                    .gpu_timer
+                    .as_ref()
+                    .filter(|_| i == 0)
+                    .map(|gpu_timer| gpu_timer.pass_writes(true, false));
+                let mut render_pass = begin_render_pass(
+                    encoder,
+                    &backdrop_blur.scene_target(),
+                    PassOps {
+                        resume: i > 0,
+                        continued: i + 1 < num_segments,
+                    },
+                    timestamp_writes,
+                );
+                for draw in segment {
+                    match draw {
+                        SceneDraw::Quads(layer, ranges) => {
+                            pipelines
+                                .quad
+                                .draw_part(&mut render_pass, layer, ranges, self.size)
+                        }
+                        SceneDraw::Panels(layer, ranges) => pipelines.quad.draw_backdrop(
+                            &mut render_pass,
+                            layer,
+                            ranges,
+                            backdrop_blur.composite_bind_group(),
+                            self.size,
+                        ),
+                        SceneDraw::Paths(layer) => {
+                            pipelines.mesh.draw(&mut render_pass, layer, self.size)
+                        }
+                    }
+                }
+            }
+
+            // copy the scene to the output and draw the remaining layers on top
+            let _span = self.trace.span("composite pass");
+            let timestamp_writes = self
+                .gpu_timer
+                .as_ref()
+                .map(|gpu_timer| gpu_timer.pass_writes(false, true));
+            let mut render_pass =
+                begin_render_pass(encoder, target, PassOps::default(), timestamp_writes);
+            backdrop_blur.blit(&mut render_pass);
+            for layer in layers[num_blurred..].iter().copied() {
+                pipelines.quad.draw(&mut render_pass, layer, self.size);
+                pipelines.mesh.draw(&mut render_pass, layer, self.size);
+            }
+            render_pass
+        } else {
+            let _span = self.trace.span("render pass");
+            let timestamp_writes = self
+                .gpu_timer
+                .as_ref()
+                .map(|gpu_timer| gpu_timer.pass_writes(true, true));
+            let mut render_pass =
+                begin_render_pass(encoder, target, PassOps::default(), timestamp_writes);
+            for layer in layers.iter().copied() {
+                pipelines.quad.draw(&mut render_pass, layer, self.size);
+                pipelines.mesh.draw(&mut render_pass, layer, self.size);
+            }
+            render_pass
+        };
+
+        // the statistics overlay goes on top of everything
+        if let Some(hud_layer) = self.hud_layer.as_ref().filter(|_| show_hud) {
+            pipelines.quad.draw(&mut render_pass, hud_layer, self.size);
+        }
+        drop(render_pass);
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.resolve(encoder);
+        }
+
+        Ok(recycled)
+    }
+
+    /// Upload the quads of the statistics overlay, growing its buffer when needed.
+    fn update_hud(&mut self, pipelines: &Pipelines) {
+        let quads = self.hud.quads(&self.worker_stats(), self.size);
+        let num_bytes = std::mem::size_of_val(quads.as_slice()) as u64;
+        let layer = self.hud_layer.get_or_insert_with(|| {
+            RenderLayer::new(&self.device_arc, &pipelines.layer_bind_group_layout)
+        });
+        let storage = match layer.buffers.take() {
+            Some(buffers) if buffers.quads.buffer.size() >= num_bytes => buffers.quads,
+            _ => construct::create_storage_buffer(
+                &self.device_arc,
+                &pipelines.quad.bind_group_layout,
+                num_bytes.next_power_of_two(),
+            ),
+        };
+        self.queue_arc
+            .write_buffer(&storage.buffer, 0, bytemuck::cast_slice(&quads));
+        layer.buffers = Some(construct::LayerBuffers {
+            quads: storage,
+            animations: None,
+            draw_ranges: vec![DrawRange {
+                start: 0,
+                end: quads.len() as u32,
+                clip: None,
+                backdrop: false,
+            }],
+            backdrop_blur: false,
+            mesh: None,
+        });
+    }
+
+    /// Move the quads of animated layers to where they are at this point in time. Their culled
+    /// quads are outdated afterwards.
+    fn animate_layers(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        animate: &animate::AnimatePipeline,
+    ) {
+        let mut animated = self
+            .workers
+            .iter_mut()
+            .flat_map(|worker| {
+                let time = worker.start_time.elapsed().as_secs_f32();
+                worker.layers.iter_mut().map(move |layer| (layer, time))
+            })
+            .filter(|(layer, _)| layer.animated.is_some())
+            .peekable();
+        if animated.peek().is_none() {
+            return;
+        }
+        let _span = self.trace.span("animate");
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("animate pass"),
+            timestamp_writes: None,
+        });
+        for (layer, time) in animated {
+            if let Some(quads) = &layer.animated {
+                animate.animate(&self.queue_arc, &mut compute_pass, quads, time);
+            }
+            if let Some(culled) = &mut layer.culled {
+                culled.stale = true;
+            }
+        }
+    }
+
+    /// Cull the quads of large layers whose camera or contents changed against the viewport,
+    /// layers that did not change keep their visible quads from earlier frames.
+    fn cull_layers(&mut self, encoder: &mut wgpu::CommandEncoder, cull: &cull::CullPipeline) {
+        let mut stale = self
+            .workers
+            .iter_mut()
+            .flat_map(|worker| worker.layers.iter_mut())
+            .filter(|layer| layer.culled().is_some_and(|culled| culled.stale))
+            .filter_map(|layer| layer.culled.as_mut())
+            .peekable();
+        if stale.peek().is_none() {
+            return;
+        }
+        let _span = self.trace.span("cull");
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("cull pass"),
+            timestamp_writes: None,
+        });
+        for culled in stale {
+            cull.cull(&mut compute_pass, culled);
+            culled.stale = false;
+        }
+    }
+
+    /// Take the messages the workers left since the last frame, never waits for them. Workers
+    /// without a new message keep their layers from earlier frames.
+    fn receive(&mut self) -> Result<Vec<(usize, construct::ConstructionWorkerMessage)>> {
+        let mut messages = Vec::new();
+        let mut error = None;
+        for (index, slot) in self.workers.iter_mut().enumerate() {
+            match slot.worker.mailbox.take() {
+                Some(message) => messages.push((index, message)),
+                // a worker only stops on its own when it failed or panicked, report why
+                None if slot.worker.mailbox.is_closed() && error.is_none() => {
+                    error = Some(
+                        slot.worker
+                            .join()
+                            .err()
+                            .unwrap_or_else(|| Error::Worker("stopped unexpectedly".to_string())),
+                    );
+                }
+                None => {}
+            }
+        }
+        let Some(error) = error else {
+            return Ok(messages);
+        };
+        // the messages taken from the other workers are never applied, return their buffers
+        for (_, message) in messages {
+            for buffers in message.layers.into_iter().filter_map(|layer| layer.buffers) {
+                for buffer in buffers.into_storage_buffers() {
+                    buffer
+                        .ready
+                        .store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Numbers of all workers combined for the statistics overlay.
+    fn worker_stats(&self) -> construct::WorkerStats {
+        self.workers
+            .iter()
+            .fold(construct::WorkerStats::default(), |total, worker| {
+                construct::WorkerStats {
+                    // the workers run in parallel
+                    construct_time: total.construct_time.max(worker.stats.construct_time),
+                    instances: total.instances + worker.stats.instances,
+                    pool_bytes: total.pool_bytes + worker.stats.pool_bytes,
+                }
+            })
+    }
+
+    /// Recompile the custom shaders that were compiled with an earlier version of the built-in
+    /// shader, which changes when it is reloaded in development mode.
+    fn update_custom_pipelines(&mut self, quad_pipeline: &QuadPipeline) {
+        for layer in self
+            .workers
+            .iter_mut()
+            .flat_map(|worker| &mut worker.layers)
+        {
+            layer.custom_pipeline = match layer.custom_pipeline.take() {
+                Some(custom) if custom.generation != quad_pipeline.generation => Some(
+                    CustomPipeline::new(quad_pipeline, custom.shader, custom.blend),
+                ),
+                custom => custom,
+            };
+        }
+    }
+
+    /// Apply the layer updates of a message, returns the buffers that are no longer in use.
+    fn update_layers(
+        &self,
+        layers: &mut Vec<RenderLayer>,
+        message: construct::ConstructionWorkerMessage,
+        pipelines: &Pipelines,
     ) -> Vec<construct::StorageBuffer> {
-        match self.construction_worker.receiver.recv() {
-            Ok(state) => {
-                // configure the render pass with the received state
-                render_pass.set_pipeline(&self.quad_pipeline.pipeline);
-                render_pass.set_bind_group(0, &state.storage_buffer.bind_group, &[]);
-                render_pass.set_vertex_buffer(0, state.storage_buffer.buffer.slice(..));
-                render_pass.draw(0..4, 0..state.num_instances);
+        let mut recycled = Vec::new();
+        for layer in layers.drain(message.layers.len()..) {
+            recycled.extend(
+                layer
+                    .buffers
+                    .into_iter()
+                    .flat_map(|b| b.into_storage_buffers()),
+            );
+        }
+        for (i, update) in message.layers.into_iter().enumerate() {
+            if i == layers.len() {
+                layers.push(RenderLayer::new(
+                    &self.device_arc,
+                    &pipelines.layer_bind_group_layout,
+                ));
+            }
+            let layer = &mut layers[i];
+            layer.blend = update.blend;
 
-                vec![state.storage_buffer]
+            // moving the camera only updates a uniform, the layer itself stays on the GPU
+            let camera = update.camera.unwrap_or(utils::Transform::IDENTITY);
+            if layer.camera != camera {
+                layer.camera = camera;
+                if let Some(culled) = &mut layer.culled {
+                    culled.stale = true;
+                }
+                self.queue_arc.write_buffer(
+                    &layer.camera_buffer,
+                    0,
+                    bytemuck::bytes_of(&CameraUniform::from(camera)),
+                );
             }
-            Err(mpsc::RecvError) => {
-                eprintln!("failed to receive construction state, empty frame");
-                vec![]
+            if layer.uniforms != update.shader.uniforms {
+                layer.uniforms = update.shader.uniforms;
+                self.queue_arc.write_buffer(
+                    &layer.uniform_buffer,
+                    0,
+                    bytemuck::cast_slice(&layer.uniforms),
+                );
             }
+
+            // custom shaders are compiled when first seen
+            layer.custom_pipeline = match (update.shader.shader, layer.custom_pipeline.take()) {
+                (None, _) => None,
+                (Some(shader), Some(custom))
+                    if Arc::ptr_eq(&shader, &custom.shader) && custom.blend == layer.blend =>
+                {
+                    Some(custom)
+                }
+                (Some(shader), _) => {
+                    Some(CustomPipeline::new(&pipelines.quad, shader, layer.blend))
+                }
+            };
+
+            // layers that did not change since the last upload are not sent again
+            if let Some(buffers) = update.buffers {
+                layer.animated = pipelines.animate.prepare(
+                    &buffers,
+                    &pipelines.quad.bind_group_layout,
+                    layer.animated.take(),
+                );
+                let source = layer
+                    .animated
+                    .as_ref()
+                    .map_or(&buffers.quads, |animated| &animated.quads);
+                layer.culled = pipelines.cull.as_ref().and_then(|cull| {
+                    cull.prepare(
+                        source,
+                        &buffers.draw_ranges,
+                        &layer.camera_buffer,
+                        &pipelines.quad.bind_group_layout,
+                        layer.culled.take(),
+                    )
+                });
+                if let Some(previous) = layer.buffers.replace(buffers) {
+                    recycled.extend(previous.into_storage_buffers());
+                }
+            }
+        }
+        recycled
+    }
+
+    /// Whether any layer has animated quads, which move on every frame without the workers
+    /// sending new contents.
+    pub fn is_animated(&self) -> bool {
+        self.workers
+            .iter()
+            .flat_map(|worker| worker.layers.iter())
+            .any(|layer| layer.animated.is_some())
+    }
+
+    /// Stop the construction workers, returns their scenes so that they can be carried over to
+    /// a new renderer.
+    pub fn stop_and_join(self) -> (Vec<construct::WorkerScene>, Result<()>) {
+        // signal all workers before waiting for any of them
+        for slot in self.workers.iter() {
+            slot.worker.stop();
         }
+        let mut scenes = Vec::new();
+        let mut result = Ok(());
+        for slot in self.workers {
+            let (scene, worker_result) = slot.worker.stop_and_join();
+            scenes.extend(scene);
+            result = result.and(worker_result);
+        }
+        (scenes, result)
     }
+}
+
+/// How a render pass treats the contents of its target, by default it clears the target and is
+/// the only pass drawing into it.
+#[derive(Default)]
+struct PassOps {
+    /// keep drawing on what an earlier pass left in the target
+    resume: bool,
+    /// a later pass resumes drawing on what this one leaves
+    continued: bool,
+}
 
-    pub fn stop_and_join(self) {
-        self.construction_worker.stop_and_join();
+fn begin_render_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    target: &RenderTarget,
+    ops: PassOps,
+    timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("render pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target.view,
+            resolve_target: target.resolve_target,
+            ops: wgpu::Operations {
+                load: match ops.resume {
+                    true => wgpu::LoadOp::Load,
+                    false => wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                },
+                // the multisampled texture is not needed anymore once it has been resolved,
+                // unless a later pass continues drawing into it
+                store: match target.resolve_target {
+                    Some(_) if !ops.continued => wgpu::StoreOp::Discard,
+                    _ => wgpu::StoreOp::Store,
+                },
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes,
+    })
+}
+
+/// Contiguous range of instances (quads) or indices (meshes) drawn with the same clip rectangle.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DrawRange {
+    pub start: u32,
+    pub end: u32,
+    pub clip: Option<utils::ClipRect>,
+    /// the range holds backdrop panels, which show a blurred copy of what was drawn before them
+    pub backdrop: bool,
+}
+
+/// Extend the last range if it uses the same clip rectangle and holds the same kind of quads,
+/// otherwise start a new one.
+pub fn push_draw_range(
+    ranges: &mut Vec<DrawRange>,
+    start: u32,
+    end: u32,
+    clip: Option<utils::ClipRect>,
+    backdrop: bool,
+) {
+    if start == end {
+        return;
+    }
+    match ranges.last_mut() {
+        Some(last) if last.end == start && last.clip == clip && last.backdrop == backdrop => {
+            last.end = end
+        }
+        _ => ranges.push(DrawRange {
+            start,
+            end,
+            clip,
+            backdrop,
+        }),
     }
 }
 
+/// Restrict drawing to the clip rectangle of a range, returns false if nothing would be visible.
+fn set_scissor(
+    render_pass: &mut wgpu::RenderPass,
+    clip: Option<utils::ClipRect>,
+    size: (u32, u32),
+) -> bool {
+    let (x, y, width, height) = match clip {
+        Some(clip) => clip.to_scissor(size.0, size.1),
+        None => (0, 0, size.0, size.1),
+    };
+    if width == 0 || height == 0 {
+        return false;
+    }
+    render_pass.set_scissor_rect(x, y, width, height);
+    true
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, NoUninit)]
 pub struct Quad {
@@ -68,31 +977,155 @@ pub struct Quad {
     pub w: f32,
     pub h: f32,
     pub color: utils::Color,
+    /// linear part of the quad's transform (column-major 2x2 matrix), applied around its center
+    pub transform: [f32; 4],
+    pub corner_radius: f32,
+    /// blur radius of the edges, used for soft shadows
+    pub blur: f32,
+    pub flags: u32,
+    pub _padding: u32,
+}
+
+pub struct Shadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub blur_radius: f32,
+    pub spread: f32,
+    pub color: utils::Color,
 }
 
-impl From<&Quad> for [f32; 8] {
-    fn from(quad: &Quad) -> [f32; 8] {
-        [
-            quad.x,
-            quad.y,
-            quad.w,
-            quad.h,
-            quad.color.r,
-            quad.color.g,
-            quad.color.b,
-            quad.color.a,
-        ]
+impl Quad {
+    pub fn new(x: f32, y: f32, w: f32, h: f32, color: utils::Color) -> Quad {
+        Quad {
+            x,
+            y,
+            w,
+            h,
+            color,
+            transform: utils::Transform::IDENTITY.linear(),
+            corner_radius: 0.0,
+            blur: 0.0,
+            flags: 0,
+            _padding: 0,
+        }
+    }
+
+    /// Box shadow for a (rounded) rectangle, should be drawn before the element itself.
+    pub fn shadow(x: f32, y: f32, w: f32, h: f32, corner_radius: f32, shadow: &Shadow) -> Quad {
+        Quad {
+            corner_radius: corner_radius + shadow.spread,
+            blur: shadow.blur_radius,
+            ..Quad::new(
+                x + shadow.offset_x,
+                y + shadow.offset_y,
+                w + shadow.spread * 2.0,
+                h + shadow.spread * 2.0,
+                shadow.color,
+            )
+        }
+    }
+
+    /// Translucent panel that blurs the scene behind it, tinted by `tint` according to its alpha.
+    pub fn backdrop_panel(x: f32, y: f32, w: f32, h: f32, tint: utils::Color) -> Quad {
+        Quad {
+            flags: QUAD_FLAG_BACKDROP_BLUR,
+            ..Quad::new(x, y, w, h, tint)
+        }
     }
+
+    pub fn is_backdrop_panel(&self) -> bool {
+        self.flags & QUAD_FLAG_BACKDROP_BLUR != 0
+    }
+
+    pub fn rounded(self, corner_radius: f32) -> Quad {
+        Quad {
+            corner_radius,
+            ..self
+        }
+    }
+
+    /// Rotate counter-clockwise around the quad's center by `angle` radians.
+    pub fn rotated(self, angle: f32) -> Quad {
+        let [a, b, c, d] = self.transform;
+        let local = utils::Transform {
+            a,
+            b,
+            c,
+            d,
+            tx: 0.0,
+            ty: 0.0,
+        };
+        Quad {
+            transform: local.then(&utils::Transform::rotation(angle)).linear(),
+            ..self
+        }
+    }
+
+    /// Place the quad in a parent coordinate system and fade it by `opacity`.
+    pub fn transformed(&self, transform: &utils::Transform, opacity: f32) -> Quad {
+        let [a, b, c, d] = self.transform;
+        let local = utils::Transform {
+            a,
+            b,
+            c,
+            d,
+            tx: self.x,
+            ty: self.y,
+        };
+        let world = local.then(transform);
+        Quad {
+            x: world.tx,
+            y: world.ty,
+            transform: world.linear(),
+            color: utils::Color {
+                a: self.color.a * opacity,
+                ..self.color
+            },
+            ..*self
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, NoUninit)]
+pub struct MeshVertex {
+    pub x: f32,
+    pub y: f32,
+    pub color: utils::Color,
 }
 
 struct QuadPipeline {
-    pipeline: wgpu::RenderPipeline,
+    device_arc: Arc<wgpu::Device>,
+    texture_out_format: wgpu::TextureFormat,
+    sample_count: u32,
+    pipeline_layout: wgpu::PipelineLayout,
+    backdrop_pipeline_layout: wgpu::PipelineLayout,
+    /// one pipeline per blend mode, indexed by `BlendMode as usize`
+    pipelines: [wgpu::RenderPipeline; 3],
+    backdrop_pipeline: wgpu::RenderPipeline,
     bind_group_layout: wgpu::BindGroupLayout,
+    backdrop_bind_group_layout: wgpu::BindGroupLayout,
+    /// shader source watched for changes in development mode
+    shader_file: Option<shader::ShaderFile>,
+    /// source of the built-in shader the pipelines were built from, custom shaders are
+    /// compiled together with it
+    quad_source: String,
+    /// incremented whenever the built-in shader is reloaded
+    generation: u32,
 }
 
 impl QuadPipeline {
-    fn new(device_arc: Arc<wgpu::Device>, texture_out_format: wgpu::TextureFormat) -> QuadPipeline {
-        let shader = device_arc.create_shader_module(include_wgsl!("quad_shader.wgsl"));
+    fn new(
+        device_arc: Arc<wgpu::Device>,
+        texture_out_format: wgpu::TextureFormat,
+        sample_count: u32,
+        layer_bind_group_layout: &wgpu::BindGroupLayout,
+        shader_file: Option<shader::ShaderFile>,
+    ) -> QuadPipeline {
+        let shader = device_arc.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("quad shader"),
+            source: wgpu::ShaderSource::Wgsl(shader::QUAD_SHADER.into()),
+        });
         let bind_group_layout =
             device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("quad bind group layout"),
@@ -108,57 +1141,375 @@ impl QuadPipeline {
                 }],
             });
 
+        let backdrop_bind_group_layout =
+            device_arc.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("backdrop bind group layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
         let render_pipeline_layout =
             device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("render pipeline layout"),
-                bind_group_layouts: &[&bind_group_layout],
+                bind_group_layouts: &[&bind_group_layout, layer_bind_group_layout],
                 push_constant_ranges: &[],
             });
-        let pipeline = device_arc.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("quad render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: size_of::<Quad>() as u64,
-                    step_mode: wgpu::VertexStepMode::Instance,
-                    attributes: &[wgpu::VertexAttribute {
-                        format: wgpu::VertexFormat::Float32x2,
-                        offset: 0,
-                        shader_location: 0,
-                    }],
+        let backdrop_pipeline_layout =
+            device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("backdrop pipeline layout"),
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    layer_bind_group_layout,
+                    &backdrop_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+        let (pipelines, backdrop_pipeline) = Self::create_pipelines(
+            &device_arc,
+            &shader,
+            (&render_pipeline_layout, &backdrop_pipeline_layout),
+            texture_out_format,
+            sample_count,
+        );
+
+        let mut quad_pipeline = QuadPipeline {
+            device_arc,
+            texture_out_format,
+            sample_count,
+            pipeline_layout: render_pipeline_layout,
+            backdrop_pipeline_layout,
+            pipelines,
+            backdrop_pipeline,
+            bind_group_layout,
+            backdrop_bind_group_layout,
+            shader_file,
+            quad_source: shader::QUAD_SHADER.to_string(),
+            generation: 0,
+        };
+        // in development mode the shader on disk replaces the embedded one right away
+        quad_pipeline.reload();
+        quad_pipeline
+    }
+
+    /// Default and backdrop pipelines for the given quad shader module.
+    fn create_pipelines(
+        device_arc: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        (layout, backdrop_layout): (&wgpu::PipelineLayout, &wgpu::PipelineLayout),
+        texture_out_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> ([wgpu::RenderPipeline; 3], wgpu::RenderPipeline) {
+        let create_pipeline = |label, layout, fragment_entry_point, blend: BlendMode| {
+            create_quad_pipeline(
+                device_arc,
+                label,
+                layout,
+                shader,
+                ("vs_main", fragment_entry_point),
+                blend.color_target(texture_out_format),
+                sample_count,
+            )
+        };
+        let pipelines = BlendMode::ALL
+            .map(|blend| create_pipeline("quad render pipeline", layout, "fs_main", blend));
+        // the panel shader outputs its coverage as alpha, mixing with the backdrop happens in the shader
+        let backdrop_pipeline = create_pipeline(
+            "quad backdrop pipeline",
+            backdrop_layout,
+            "fs_backdrop",
+            BlendMode::Alpha,
+        );
+        (pipelines, backdrop_pipeline)
+    }
+
+    /// Rebuild the pipelines if the watched shader file changed, keeping the current pipelines
+    /// if the new shader fails to compile.
+    fn reload(&mut self) {
+        let Some(shader_file) = self.shader_file.as_mut() else {
+            return;
+        };
+        let Some(source) = shader_file.poll() else {
+            return;
+        };
+        let path = shader_file.path.display();
+        if let Err(e) = shader::validate(&source) {
+            log::error!(
+                "failed to compile {}, keeping the previous shader\n{}",
+                path,
+                e
+            );
+            return;
+        }
+
+        self.device_arc
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = self
+            .device_arc
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("quad shader"),
+                source: wgpu::ShaderSource::Wgsl(source.as_str().into()),
+            });
+        let (pipelines, backdrop_pipeline) = Self::create_pipelines(
+            &self.device_arc,
+            &module,
+            (&self.pipeline_layout, &self.backdrop_pipeline_layout),
+            self.texture_out_format,
+            self.sample_count,
+        );
+        match self.device_arc.pop_error_scope().block_on() {
+            Some(e) => log::error!(
+                "failed to build {}, keeping the previous shader\n{}",
+                path,
+                e
+            ),
+            None => {
+                log::info!("loaded {}", path);
+                self.pipelines = pipelines;
+                self.backdrop_pipeline = backdrop_pipeline;
+                self.quad_source = source;
+                self.generation += 1;
+            }
+        }
+    }
+
+    /// Build the pipeline for a custom shader, returns the validation error if it fails.
+    fn create_custom(
+        &self,
+        shader: &shader::CustomShader,
+        blend: BlendMode,
+    ) -> Result<wgpu::RenderPipeline> {
+        self.device_arc
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = self
+            .device_arc
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("custom quad shader"),
+                source: wgpu::ShaderSource::Wgsl(shader.source(&self.quad_source).into()),
+            });
+        let pipeline = create_quad_pipeline(
+            &self.device_arc,
+            "custom quad pipeline",
+            &self.pipeline_layout,
+            &module,
+            (shader.vertex_entry_point, shader::FRAGMENT_ENTRY_POINT),
+            blend.color_target(self.texture_out_format),
+            self.sample_count,
+        );
+        match self.device_arc.pop_error_scope().block_on() {
+            Some(e) => Err(Error::Shader(e.to_string())),
+            None => Ok(pipeline),
+        }
+    }
+
+    fn draw(&self, render_pass: &mut wgpu::RenderPass, layer: &RenderLayer, size: (u32, u32)) {
+        let num_ranges = layer.buffers.as_ref().map_or(0, |b| b.draw_ranges.len());
+        self.draw_part(render_pass, layer, 0..num_ranges, size);
+    }
+
+    /// Draw the quads of the draw ranges with the given indices.
+    fn draw_part(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        layer: &RenderLayer,
+        ranges: std::ops::Range<usize>,
+        size: (u32, u32),
+    ) {
+        let pipeline = match &layer.custom_pipeline {
+            Some(CustomPipeline {
+                pipeline: Some(pipeline),
+                ..
+            }) => pipeline,
+            _ => &self.pipelines[layer.blend as usize],
+        };
+        render_pass.set_pipeline(pipeline);
+        Self::draw_ranges(render_pass, layer, ranges, size);
+    }
+
+    /// Draw the backdrop panels of the draw ranges with the given indices over the blurred scene.
+    fn draw_backdrop(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        layer: &RenderLayer,
+        ranges: std::ops::Range<usize>,
+        composite_bind_group: &wgpu::BindGroup,
+        size: (u32, u32),
+    ) {
+        render_pass.set_pipeline(&self.backdrop_pipeline);
+        render_pass.set_bind_group(2, composite_bind_group, &[]);
+        Self::draw_ranges(render_pass, layer, ranges, size);
+    }
+
+    fn draw_ranges(
+        render_pass: &mut wgpu::RenderPass,
+        layer: &RenderLayer,
+        ranges: std::ops::Range<usize>,
+        size: (u32, u32),
+    ) {
+        let Some(buffers) = &layer.buffers else {
+            return;
+        };
+        let culled = layer.culled();
+        let quads = match (culled, &layer.animated) {
+            (Some(culled), _) => &culled.quads,
+            (None, Some(animated)) => &animated.quads,
+            (None, None) => &buffers.quads,
+        };
+        render_pass.set_bind_group(0, &quads.bind_group, &[]);
+        render_pass.set_bind_group(1, &layer.layer_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, quads.buffer.slice(..));
+        for (i, range) in ranges.clone().zip(&buffers.draw_ranges[ranges]) {
+            if set_scissor(render_pass, layer.clip(range), size) {
+                match culled {
+                    Some(culled) => culled.draw(render_pass, i),
+                    None => render_pass.draw(0..4, range.start..range.end),
+                }
+            }
+        }
+    }
+}
+
+/// Pipeline drawing instanced quads as triangle strips with the given vertex and fragment entry points.
+fn create_quad_pipeline(
+    device_arc: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_points: (&str, &str),
+    target: wgpu::ColorTargetState,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    device_arc.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some(entry_points.0),
+            buffers: &[wgpu::VertexBufferLayout {
+                array_stride: size_of::<Quad>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &[wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x2,
+                    offset: 0,
+                    shader_location: 0,
                 }],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: texture_out_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Cw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
+            }],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some(entry_points.1),
+            targets: &[Some(target)],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            // mirroring transforms flip the winding order of a quad
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+struct MeshPipeline {
+    /// one pipeline per blend mode, indexed by `BlendMode as usize`
+    pipelines: [wgpu::RenderPipeline; 3],
+}
+
+impl MeshPipeline {
+    fn new(
+        device_arc: Arc<wgpu::Device>,
+        texture_out_format: wgpu::TextureFormat,
+        sample_count: u32,
+        layer_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> MeshPipeline {
+        let shader = device_arc.create_shader_module(include_wgsl!("mesh_shader.wgsl"));
+        let render_pipeline_layout =
+            device_arc.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("mesh pipeline layout"),
+                bind_group_layouts: &[layer_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipelines = BlendMode::ALL.map(|blend| {
+            device_arc.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("mesh render pipeline"),
+                layout: Some(&render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: size_of::<MeshVertex>() as u64,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+                    }],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(blend.color_target(texture_out_format))],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // the tessellator does not guarantee a consistent winding order
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: None,
+            })
         });
 
-        QuadPipeline {
-            pipeline,
-            bind_group_layout,
+        MeshPipeline { pipelines }
+    }
+
+    fn draw(&self, render_pass: &mut wgpu::RenderPass, layer: &RenderLayer, size: (u32, u32)) {
+        if let Some(mesh) = layer.buffers.as_ref().and_then(|b| b.mesh.as_ref()) {
+            render_pass.set_pipeline(&self.pipelines[layer.blend as usize]);
+            render_pass.set_bind_group(0, &layer.layer_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, mesh.vertices.buffer.slice(..));
+            render_pass.set_index_buffer(mesh.indices.buffer.slice(..), wgpu::IndexFormat::Uint32);
+            for range in mesh.draw_ranges.iter() {
+                if set_scissor(render_pass, layer.clip(range), size) {
+                    render_pass.draw_indexed(range.start..range.end, 0, 0..1);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,426 @@
+use crate::animate;
+use crate::path;
+use crate::render;
+use crate::ui;
+use crate::utils;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+pub enum NodeKind {
+    Group,
+    /// batch of quads in the node's local coordinate system
+    Quads(Vec<render::Quad>),
+    /// batch of quads moved on the GPU, with one animation per quad
+    AnimatedQuads(Vec<render::Quad>, Vec<animate::Animation>),
+    Path(lyon::path::Path, path::PathStyle),
+}
+
+pub struct Node {
+    children: Vec<NodeId>,
+    transform: utils::Transform,
+    /// clip rectangle in the node's local coordinate system, applied to the whole subtree
+    clip: Option<utils::ClipRect>,
+    visible: bool,
+    opacity: f32,
+    kind: NodeKind,
+    /// transform, clip or opacity changed since the last `flatten`
+    dirty: bool,
+}
+
+/// Retained tree of scene nodes. Changes only mark the scene as dirty, the flat list of quads
+/// and paths uploaded to the GPU is updated by `flatten` once per frame at most.
+pub struct Scene {
+    nodes: Vec<Node>,
+    /// nodes were added or shown or hidden, so the draw list has to be rebuilt
+    structure_dirty: bool,
+    /// some nodes are dirty, so the draw list has to be patched
+    changed: bool,
+}
+
+impl Scene {
+    pub const ROOT: NodeId = NodeId(0);
+
+    pub fn new() -> Self {
+        Scene {
+            nodes: vec![Node {
+                children: Vec::new(),
+                transform: utils::Transform::IDENTITY,
+                clip: None,
+                visible: true,
+                opacity: 1.0,
+                kind: NodeKind::Group,
+                dirty: false,
+            }],
+            structure_dirty: false,
+            changed: false,
+        }
+    }
+
+    pub fn add_node(
+        &mut self,
+        parent: NodeId,
+        transform: utils::Transform,
+        kind: NodeKind,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            children: Vec::new(),
+            transform,
+            clip: None,
+            visible: true,
+            opacity: 1.0,
+            kind,
+            dirty: false,
+        });
+        self.nodes[parent.0].children.push(id);
+        self.structure_dirty = true;
+        id
+    }
+
+    pub fn add_group(&mut self, parent: NodeId, transform: utils::Transform) -> NodeId {
+        self.add_node(parent, transform, NodeKind::Group)
+    }
+
+    pub fn add_quads(&mut self, parent: NodeId, quads: Vec<render::Quad>) -> NodeId {
+        self.add_node(parent, utils::Transform::IDENTITY, NodeKind::Quads(quads))
+    }
+
+    /// Add quads that move by themselves, one animation per quad. Unlike changing quads, this
+    /// does not upload the layer again every frame.
+    pub fn add_animated_quads(
+        &mut self,
+        parent: NodeId,
+        quads: Vec<render::Quad>,
+        animations: Vec<animate::Animation>,
+    ) -> NodeId {
+        assert_eq!(quads.len(), animations.len(), "one animation per quad");
+        self.add_node(
+            parent,
+            utils::Transform::IDENTITY,
+            NodeKind::AnimatedQuads(quads, animations),
+        )
+    }
+
+    pub fn add_path(
+        &mut self,
+        parent: NodeId,
+        path: lyon::path::Path,
+        style: path::PathStyle,
+    ) -> NodeId {
+        self.add_node(
+            parent,
+            utils::Transform::IDENTITY,
+            NodeKind::Path(path, style),
+        )
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: utils::Transform) {
+        if self.nodes[id.0].transform != transform {
+            self.nodes[id.0].transform = transform;
+            self.mark_dirty(id);
+        }
+    }
+
+    pub fn set_clip(&mut self, id: NodeId, clip: Option<utils::ClipRect>) {
+        if self.nodes[id.0].clip != clip {
+            self.nodes[id.0].clip = clip;
+            self.mark_dirty(id);
+        }
+    }
+
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) {
+        if self.nodes[id.0].visible != visible {
+            self.nodes[id.0].visible = visible;
+            self.structure_dirty = true;
+        }
+    }
+
+    pub fn set_opacity(&mut self, id: NodeId, opacity: f32) {
+        if self.nodes[id.0].opacity != opacity {
+            self.nodes[id.0].opacity = opacity;
+            self.mark_dirty(id);
+        }
+    }
+
+    /// Update the draw list from the scene graph. Only the quads and paths of dirty subtrees are
+    /// replaced in place, unless the structure of the scene changed and the list is rebuilt.
+    pub fn flatten(&mut self, draw_list: &mut ui::DrawList) {
+        if !self.structure_dirty && !self.changed {
+            return;
+        }
+        let rebuild = self.structure_dirty;
+        draw_list.begin(!rebuild);
+        self.flatten_node(Self::ROOT, 1.0, draw_list, rebuild);
+        draw_list.finish();
+        for node in self.nodes.iter_mut() {
+            node.dirty = false;
+        }
+        self.structure_dirty = false;
+        self.changed = false;
+    }
+
+    /// Add the contents of a visible subtree. Nodes below a dirty node are added again, the
+    /// others only skip what they added in the previous pass.
+    fn flatten_node(
+        &self,
+        id: NodeId,
+        parent_opacity: f32,
+        draw_list: &mut ui::DrawList,
+        parent_dirty: bool,
+    ) {
+        let node = &self.nodes[id.0];
+        if !node.visible {
+            return;
+        }
+        let opacity = parent_opacity * node.opacity;
+        let dirty = parent_dirty || node.dirty;
+
+        draw_list.push_transform(node.transform);
+        if let Some(clip) = node.clip {
+            draw_list.push_clip(clip);
+        }
+
+        match &node.kind {
+            NodeKind::Group => {}
+            NodeKind::Quads(quads) if dirty => draw_list.add_quads(quads, None, opacity),
+            NodeKind::AnimatedQuads(quads, animations) if dirty => {
+                draw_list.add_quads(quads, Some(animations), opacity)
+            }
+            NodeKind::Quads(_) | NodeKind::AnimatedQuads(..) => draw_list.skip_quads(),
+            NodeKind::Path(path, style) if dirty => {
+                draw_list.add_path(path.clone(), style.with_opacity(opacity))
+            }
+            NodeKind::Path(..) => draw_list.skip_path(),
+        }
+        for child in node.children.iter() {
+            self.flatten_node(*child, opacity, draw_list, dirty);
+        }
+
+        if node.clip.is_some() {
+            draw_list.pop_clip();
+        }
+        draw_list.pop_transform();
+    }
+
+    fn mark_dirty(&mut self, id: NodeId) {
+        self.nodes[id.0].dirty = true;
+        self.changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: utils::Color = utils::Color {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+        a: 1.0,
+    };
+
+    struct Nodes {
+        panel: NodeId,
+        content: NodeId,
+        spinner: NodeId,
+        path: NodeId,
+        hidden: NodeId,
+    }
+
+    /// Scene with nested transforms, clips, backdrop panels, animated quads and paths.
+    fn build(layer: &mut ui::Layer) -> Nodes {
+        let scene = &mut layer.scene;
+        scene.add_quads(
+            Scene::ROOT,
+            vec![render::Quad::new(0.0, 0.0, 2.0, 2.0, WHITE)],
+        );
+        let panel = scene.add_group(Scene::ROOT, utils::Transform::translation(0.2, 0.1));
+        scene.add_quads(
+            panel,
+            vec![
+                render::Quad::new(0.0, 0.0, 0.6, 0.6, WHITE),
+                render::Quad::backdrop_panel(0.0, 0.0, 0.5, 0.5, WHITE),
+                render::Quad::new(0.0, 0.1, 0.2, 0.1, WHITE),
+            ],
+        );
+        let content = scene.add_group(panel, utils::Transform::IDENTITY);
+        scene.set_clip(
+            content,
+            Some(utils::ClipRect {
+                x: 0.0,
+                y: 0.0,
+                w: 0.4,
+                h: 0.4,
+            }),
+        );
+        let mut builder = path::PathBuilder::new();
+        builder
+            .move_to(-0.1, -0.1)
+            .line_to(0.1, -0.1)
+            .line_to(0.0, 0.1)
+            .close();
+        let path = scene.add_path(
+            content,
+            builder.build(),
+            path::PathStyle {
+                fill: Some(path::Fill {
+                    color: WHITE,
+                    rule: path::FillRule::NonZero,
+                }),
+                stroke: None,
+            },
+        );
+        scene.add_quads(content, vec![render::Quad::new(0.1, 0.1, 0.3, 0.3, WHITE)]);
+        let spinner = scene.add_group(Scene::ROOT, utils::Transform::translation(-0.5, 0.5));
+        let animation = animate::Animation::keyframes(
+            [0.0, 0.0],
+            [0.1, 0.0],
+            1.0,
+            animate::Easing::Linear,
+            animate::Repeat::Loop,
+        );
+        scene.add_animated_quads(
+            spinner,
+            vec![render::Quad::new(0.0, 0.0, 0.1, 0.1, WHITE); 2],
+            vec![animation; 2],
+        );
+        let hidden = scene.add_quads(
+            Scene::ROOT,
+            vec![render::Quad::new(0.0, 0.0, 1.0, 1.0, WHITE)],
+        );
+        scene.set_visible(hidden, false);
+        scene.add_quads(
+            Scene::ROOT,
+            vec![render::Quad::new(0.5, 0.5, 0.1, 0.1, WHITE)],
+        );
+        Nodes {
+            panel,
+            content,
+            spinner,
+            path,
+            hidden,
+        }
+    }
+
+    fn assert_same(patched: &ui::Layer, rebuilt: &ui::Layer) {
+        let quad_bytes = |layer: &ui::Layer| bytemuck::cast_slice::<_, u8>(layer.quads()).to_vec();
+        assert_eq!(quad_bytes(patched), quad_bytes(rebuilt));
+        let animation_bytes =
+            |layer: &ui::Layer| bytemuck::cast_slice::<_, u8>(layer.animations()).to_vec();
+        assert_eq!(animation_bytes(patched), animation_bytes(rebuilt));
+        assert!(patched.quad_draw_ranges() == rebuilt.quad_draw_ranges());
+        let vertex_bytes =
+            |layer: &ui::Layer| bytemuck::cast_slice::<_, u8>(&layer.mesh().vertices).to_vec();
+        assert_eq!(vertex_bytes(patched), vertex_bytes(rebuilt));
+        assert_eq!(patched.mesh().indices, rebuilt.mesh().indices);
+        assert!(patched.mesh_draw_ranges() == rebuilt.mesh_draw_ranges());
+    }
+
+    /// Flatten a layer, change it and flatten it again, comparing the patched draw list with one
+    /// built from scratch with the same changes. Returns the versions before and after the change.
+    fn check_patch(change: impl Fn(&mut Scene, &Nodes)) -> (u64, u64) {
+        let mut patched = ui::Layer::new("patched", render::BlendMode::Alpha);
+        let nodes = build(&mut patched);
+        patched.flatten();
+        let version = patched.version();
+        change(&mut patched.scene, &nodes);
+        patched.flatten();
+
+        let mut rebuilt = ui::Layer::new("rebuilt", render::BlendMode::Alpha);
+        let nodes = build(&mut rebuilt);
+        change(&mut rebuilt.scene, &nodes);
+        rebuilt.flatten();
+
+        assert_same(&patched, &rebuilt);
+        (version, patched.version())
+    }
+
+    #[test]
+    fn patch_transform() {
+        let (before, after) = check_patch(|scene, nodes| {
+            scene.set_transform(
+                nodes.panel,
+                utils::Transform::rotation(0.4).then(&utils::Transform::translation(-0.1, 0.3)),
+            );
+            scene.set_transform(nodes.spinner, utils::Transform::scale(2.0, 0.5));
+        });
+        assert!(after > before);
+    }
+
+    #[test]
+    fn patch_opacity_and_clip() {
+        check_patch(|scene, nodes| {
+            scene.set_opacity(nodes.panel, 0.5);
+            scene.set_opacity(nodes.path, 0.25);
+            scene.set_clip(
+                nodes.content,
+                Some(utils::ClipRect {
+                    x: 0.1,
+                    y: 0.0,
+                    w: 0.2,
+                    h: 0.6,
+                }),
+            );
+        });
+    }
+
+    #[test]
+    fn patch_removed_clip() {
+        check_patch(|scene, nodes| scene.set_clip(nodes.content, None));
+    }
+
+    #[test]
+    fn rebuild_on_visibility() {
+        check_patch(|scene, nodes| {
+            scene.set_visible(nodes.hidden, true);
+            scene.set_visible(nodes.content, false);
+        });
+    }
+
+    #[test]
+    fn rebuild_on_added_node() {
+        check_patch(|scene, nodes| {
+            scene.add_quads(
+                nodes.content,
+                vec![render::Quad::new(0.0, 0.0, 0.1, 0.1, WHITE)],
+            );
+            scene.set_transform(nodes.panel, utils::Transform::translation(0.0, 0.0));
+        });
+    }
+
+    #[test]
+    fn unchanged_scene_keeps_version() {
+        let (before, after) = check_patch(|scene, nodes| {
+            // setting the same values is no change
+            scene.set_transform(nodes.spinner, utils::Transform::translation(-0.5, 0.5));
+            scene.set_opacity(nodes.panel, 1.0);
+        });
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn patches_in_turn() {
+        let mut patched = ui::Layer::new("patched", render::BlendMode::Alpha);
+        let nodes = build(&mut patched);
+        patched.flatten();
+        for i in 0..3 {
+            let angle = i as f32 * 0.5;
+            patched
+                .scene
+                .set_transform(nodes.spinner, utils::Transform::rotation(angle));
+            patched.flatten();
+            patched.scene.set_opacity(nodes.path, 1.0 - angle * 0.2);
+            patched.flatten();
+        }
+
+        let mut rebuilt = ui::Layer::new("rebuilt", render::BlendMode::Alpha);
+        let nodes = build(&mut rebuilt);
+        rebuilt
+            .scene
+            .set_transform(nodes.spinner, utils::Transform::rotation(1.0));
+        rebuilt.scene.set_opacity(nodes.path, 0.8);
+        rebuilt.flatten();
+        assert_same(&patched, &rebuilt);
+    }
+}
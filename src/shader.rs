@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use wgpu::naga;
+
+/// Built-in quad shader, user shaders are compiled together with it so they can use its
+/// structs, bindings and helper functions.
+pub const QUAD_SHADER: &str = include_str!("quad_shader.wgsl");
+
+/// Fragment entry point a custom shader has to define.
+pub const FRAGMENT_ENTRY_POINT: &str = "fs_custom";
+/// Optional vertex entry point of a custom shader, replaces `vs_main` if present.
+const VERTEX_ENTRY_POINT: &str = "vs_custom";
+
+/// User uniforms of a layer, `uniforms: array<vec4<f32>, 16>` in WGSL.
+pub type Uniforms = [[f32; 4]; 16];
+
+/// User-provided WGSL replacing the quad shading of a layer.
+pub struct CustomShader {
+    user_source: String,
+    pub vertex_entry_point: &'static str,
+}
+
+impl CustomShader {
+    /// Validate the user code, returns a printable error message if it does not compile.
+    pub fn new(user_source: &str) -> Result<CustomShader, String> {
+        let module = validate(&combine(user_source, QUAD_SHADER))?;
+
+        let has_entry_point = |name: &str, stage: naga::ShaderStage| {
+            module
+                .entry_points
+                .iter()
+                .any(|entry_point| entry_point.name == name && entry_point.stage == stage)
+        };
+        if !has_entry_point(FRAGMENT_ENTRY_POINT, naga::ShaderStage::Fragment) {
+            return Err(format!(
+                "custom shader has no `@fragment fn {}(in: VertexOut)`",
+                FRAGMENT_ENTRY_POINT
+            ));
+        }
+        let vertex_entry_point =
+            match has_entry_point(VERTEX_ENTRY_POINT, naga::ShaderStage::Vertex) {
+                true => VERTEX_ENTRY_POINT,
+                false => "vs_main",
+            };
+
+        Ok(CustomShader {
+            user_source: user_source.to_string(),
+            vertex_entry_point,
+        })
+    }
+
+    /// User code followed by the given quad shader, which is the one loaded from disk in
+    /// development mode.
+    pub fn source(&self, quad_shader: &str) -> String {
+        combine(&self.user_source, quad_shader)
+    }
+
+    /// Whether the shader replaces the vertex shader, which may move quads anywhere.
+    pub fn has_custom_vertex(&self) -> bool {
+        self.vertex_entry_point == VERTEX_ENTRY_POINT
+    }
+}
+
+fn combine(user_source: &str, quad_shader: &str) -> String {
+    // user code goes first so that line numbers in error messages match the user's source
+    format!("{}\n{}", user_source, quad_shader)
+}
+
+#[derive(Clone, Default)]
+pub struct LayerShader {
+    pub shader: Option<Arc<CustomShader>>,
+    pub uniforms: Uniforms,
+}
+
+impl LayerShader {
+    /// Pack a flat list of floats into the uniform array, returns false if there are too many.
+    pub fn set_uniforms(&mut self, values: &[f32]) -> bool {
+        if values.len() > 4 * self.uniforms.len() {
+            return false;
+        }
+        self.uniforms = Uniforms::default();
+        for (i, value) in values.iter().enumerate() {
+            self.uniforms[i / 4][i % 4] = *value;
+        }
+        true
+    }
+}
+
+/// Custom shaders and uniforms by layer name, shared between Python and the construction worker.
+pub type LayerShaders = Arc<Mutex<HashMap<String, LayerShader>>>;
+
+#[derive(Clone, Default)]
+pub struct ShaderSettings {
+    /// custom shaders and uniforms by layer name, may be changed while the window is open
+    pub layer_shaders: LayerShaders,
+    /// development mode: load the built-in quad shader from this directory and reload it on changes
+    pub shader_dir: Option<PathBuf>,
+}
+
+/// Parse and validate WGSL, returns a printable error message if it does not compile.
+pub fn validate(source: &str) -> Result<naga::Module, String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|e| e.emit_to_string(source))?;
+    Ok(module)
+}
+
+/// Shader source on disk that is checked for changes, used to iterate on shaders without rebuilding.
+pub struct ShaderFile {
+    pub path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+}
+
+impl ShaderFile {
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new(path: PathBuf) -> Self {
+        ShaderFile {
+            path,
+            modified: None,
+            last_check: None,
+        }
+    }
+
+    /// Returns the new source if the file changed since the last call.
+    pub fn poll(&mut self) -> Option<String> {
+        if self
+            .last_check
+            .is_some_and(|last_check| last_check.elapsed() < Self::POLL_INTERVAL)
+        {
+            return None;
+        }
+        let first_check = self.last_check.is_none();
+        self.last_check = Some(Instant::now());
+
+        let modified = match std::fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                // only report the file going missing once
+                if first_check || self.modified.is_some() {
+                    log::warn!("failed to watch {}: {}", self.path.display(), e);
+                }
+                self.modified = None;
+                return None;
+            }
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        match std::fs::read_to_string(&self.path) {
+            Ok(source) => Some(source),
+            Err(e) => {
+                log::warn!("failed to read {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::{atomic, Arc};
+
+/// Number of measurements that can be waiting for their readback, further ones are skipped.
+const MAX_IN_FLIGHT: usize = 4;
+const QUERY_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+
+/// Buffer the resolved timestamps are copied to, mapped for reading once the GPU is done.
+struct Readback {
+    buffer: wgpu::Buffer,
+    in_use: bool,
+    mapped: Arc<atomic::AtomicBool>,
+}
+
+/// Measures the GPU time between a begin and an end timestamp, the results are read back a few
+/// frames later without waiting for the GPU.
+pub struct GpuTimer {
+    device_arc: Arc<wgpu::Device>,
+    label: &'static str,
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readbacks: Vec<Readback>,
+    /// readback recorded into the current encoder, mapped once it has been submitted
+    pending: Option<usize>,
+    /// nanoseconds per timestamp tick
+    period: f64,
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device was created without `TIMESTAMP_QUERY`.
+    pub fn new(
+        device_arc: Arc<wgpu::Device>,
+        queue: &wgpu::Queue,
+        label: &'static str,
+    ) -> Option<GpuTimer> {
+        if !device_arc
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            return None;
+        }
+        let query_set = device_arc.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some(label),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device_arc.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: 2 * QUERY_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Some(GpuTimer {
+            device_arc,
+            label,
+            query_set,
+            resolve_buffer,
+            readbacks: Vec::new(),
+            pending: None,
+            period: queue.get_timestamp_period() as f64,
+        })
+    }
+
+    /// Timestamp writes of a render pass, `begin` and `end` select which ends of the pass are
+    /// measured so that a measurement can span several passes.
+    pub fn pass_writes(&self, begin: bool, end: bool) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: begin.then_some(0),
+            end_of_pass_write_index: end.then_some(1),
+        }
+    }
+
+    /// Write the begin timestamp outside of a pass, requires `TIMESTAMP_QUERY_INSIDE_ENCODERS`.
+    pub fn write_begin(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 0);
+    }
+
+    /// Write the end timestamp outside of a pass, requires `TIMESTAMP_QUERY_INSIDE_ENCODERS`.
+    pub fn write_end(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.query_set, 1);
+    }
+
+    /// Resolve the timestamps written into `encoder` and copy them to a free readback buffer.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let index = match self.readbacks.iter().position(|readback| !readback.in_use) {
+            Some(index) => index,
+            None if self.readbacks.len() < MAX_IN_FLIGHT => {
+                self.readbacks.push(Readback {
+                    buffer: self.device_arc.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(self.label),
+                        size: 2 * QUERY_SIZE,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    in_use: false,
+                    mapped: Arc::default(),
+                });
+                self.readbacks.len() - 1
+            }
+            // all readbacks are still waiting for the GPU, skip this measurement
+            None => return,
+        };
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readbacks[index].buffer,
+            0,
+            2 * QUERY_SIZE,
+        );
+        self.readbacks[index].in_use = true;
+        self.pending = Some(index);
+    }
+
+    /// Request the readback of the last resolve, to be called after its encoder was submitted.
+    pub fn map(&mut self) {
+        let Some(index) = self.pending.take() else {
+            return;
+        };
+        let mapped = self.readbacks[index].mapped.clone();
+        self.readbacks[index]
+            .buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // a failed mapping keeps the readback in use, the device is most likely lost
+                if result.is_ok() {
+                    mapped.store(true, atomic::Ordering::SeqCst);
+                }
+            });
+    }
+
+    /// Durations in seconds of the measurements whose readback has completed.
+    pub fn read(&mut self) -> Vec<f64> {
+        let mut durations = Vec::new();
+        for readback in self.readbacks.iter_mut() {
+            if !readback.mapped.swap(false, atomic::Ordering::SeqCst) {
+                continue;
+            }
+            {
+                let view = readback.buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&view);
+                if let Some(ticks) = timestamps[1].checked_sub(timestamps[0]) {
+                    durations.push(ticks as f64 * self.period * 1e-9);
+                }
+            }
+            readback.buffer.unmap();
+            readback.in_use = false;
+        }
+        durations
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::{atomic, Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::Instant;
+
+/// Events kept before further ones are dropped, roughly 100 MB of JSON.
+const MAX_EVENTS: usize = 1 << 20;
+
+enum Phase {
+    /// span with its duration in microseconds
+    Complete(f64),
+    /// start of an arrow to the matching `FlowEnd`, e.g. a message sent to another thread
+    FlowStart(u64),
+    FlowEnd(u64),
+}
+
+struct Event {
+    name: &'static str,
+    phase: Phase,
+    thread: usize,
+    /// microseconds since the trace was started
+    timestamp: f64,
+    args: Vec<(&'static str, String)>,
+}
+
+#[derive(Default)]
+struct Events {
+    events: Vec<Event>,
+    /// names of the threads that recorded events, indexed by `Event::thread`
+    threads: Vec<(ThreadId, String)>,
+    dropped: u64,
+}
+
+impl Events {
+    fn thread(&mut self) -> usize {
+        let current = std::thread::current();
+        match self.threads.iter().position(|(id, _)| *id == current.id()) {
+            Some(index) => index,
+            None => {
+                let name = current.name().unwrap_or("thread").to_string();
+                self.threads.push((current.id(), name));
+                self.threads.len() - 1
+            }
+        }
+    }
+
+    fn push(&mut self, mut event: Event) {
+        if self.events.len() == MAX_EVENTS {
+            self.dropped += 1;
+            return;
+        }
+        event.thread = self.thread();
+        self.events.push(event);
+    }
+}
+
+struct Trace {
+    start: Instant,
+    events: Mutex<Events>,
+    /// last id handed out by `flow_id`
+    flow_ids: atomic::AtomicU64,
+}
+
+impl Trace {
+    fn timestamp(&self, instant: Instant) -> f64 {
+        instant.duration_since(self.start).as_secs_f64() * 1e6
+    }
+}
+
+/// Records spans of all threads for a Chrome Trace Event file, viewable in Perfetto.
+/// The default tracer is disabled and records nothing.
+#[derive(Clone, Default)]
+pub struct Tracer(Option<Arc<Trace>>);
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer(Some(Arc::new(Trace {
+            start: Instant::now(),
+            events: Mutex::default(),
+            flow_ids: atomic::AtomicU64::new(0),
+        })))
+    }
+
+    /// Id for a new arrow, unique among all windows recording into this trace.
+    pub fn flow_id(&self) -> u64 {
+        self.0.as_ref().map_or(0, |trace| {
+            trace.flow_ids.fetch_add(1, atomic::Ordering::Relaxed) + 1
+        })
+    }
+
+    /// Start a span on the current thread that ends when the returned guard is dropped.
+    pub fn span(&self, name: &'static str) -> Span {
+        Span {
+            trace: self.0.clone(),
+            name,
+            start: Instant::now(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Start an arrow in the innermost span of the current thread, ending in the span that
+    /// calls `flow_end` with the same `id`.
+    pub fn flow_start(&self, name: &'static str, id: u64) {
+        self.instant(name, Phase::FlowStart(id));
+    }
+
+    pub fn flow_end(&self, name: &'static str, id: u64) {
+        self.instant(name, Phase::FlowEnd(id));
+    }
+
+    fn instant(&self, name: &'static str, phase: Phase) {
+        let Some(trace) = &self.0 else {
+            return;
+        };
+        let timestamp = trace.timestamp(Instant::now());
+        trace.events.lock().unwrap().push(Event {
+            name,
+            phase,
+            thread: 0,
+            timestamp,
+            args: Vec::new(),
+        });
+    }
+
+    /// Write the recorded events in the Chrome Trace Event JSON format.
+    pub fn write(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let Some(trace) = &self.0 else {
+            return Ok(());
+        };
+        let events = trace.events.lock().unwrap();
+        if events.dropped > 0 {
+            log::warn!(
+                "trace is incomplete, {} events after the first {} were dropped",
+                events.dropped,
+                MAX_EVENTS
+            );
+        }
+        let pid = std::process::id();
+
+        writeln!(writer, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        let mut separator = "";
+        for (tid, (_, name)) in events.threads.iter().enumerate() {
+            writeln!(
+                writer,
+                "{}{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":{},\"tid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+                separator,
+                pid,
+                tid,
+                escape(name)
+            )?;
+            separator = ",";
+        }
+        for event in events.events.iter() {
+            let phase = match event.phase {
+                Phase::Complete(duration) => format!("\"ph\":\"X\",\"dur\":{:.3}", duration),
+                Phase::FlowStart(id) => format!("\"ph\":\"s\",\"id\":{}", id),
+                // bind to the enclosing span rather than the next one to start
+                Phase::FlowEnd(id) => format!("\"ph\":\"f\",\"bp\":\"e\",\"id\":{}", id),
+            };
+            let mut args = String::new();
+            for (i, (key, value)) in event.args.iter().enumerate() {
+                if i > 0 {
+                    args.push(',');
+                }
+                let _ = write!(args, "\"{}\":\"{}\"", key, escape(value));
+            }
+            writeln!(
+                writer,
+                "{}{{\"name\":\"{}\",\"cat\":\"guilible\",{},\"ts\":{:.3},\"pid\":{},\"tid\":{},\"args\":{{{}}}}}",
+                separator,
+                event.name,
+                phase,
+                event.timestamp,
+                pid,
+                event.thread,
+                args
+            )?;
+            separator = ",";
+        }
+        writeln!(writer, "]}}")?;
+        writer.flush()
+    }
+}
+
+/// Span of a `Tracer`, recorded when dropped.
+pub struct Span {
+    trace: Option<Arc<Trace>>,
+    name: &'static str,
+    start: Instant,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    /// Attach a value shown with the span in the trace viewer.
+    pub fn arg(mut self, key: &'static str, value: impl std::fmt::Display) -> Span {
+        if self.trace.is_some() {
+            self.args.push((key, value.to_string()));
+        }
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+        let end = Instant::now();
+        trace.events.lock().unwrap().push(Event {
+            name: self.name,
+            phase: Phase::Complete(end.duration_since(self.start).as_secs_f64() * 1e6),
+            thread: 0,
+            timestamp: trace.timestamp(self.start),
+            args: std::mem::take(&mut self.args),
+        });
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_json_strings() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("say \"hi\""), "say \\\"hi\\\"");
+        assert_eq!(escape("C:\\path"), "C:\\\\path");
+        assert_eq!(escape("a\nb\t\u{1}"), "a\\u000ab\\u0009\\u0001");
+        assert_eq!(escape("ümlaut ✓"), "ümlaut ✓");
+    }
+
+    #[test]
+    fn flow_ids_are_unique() {
+        let tracer = Tracer::new();
+        // windows of one trace share clones of the tracer
+        let other = tracer.clone();
+        let ids = [tracer.flow_id(), other.flow_id(), tracer.flow_id()];
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(Tracer::default().flow_id(), 0);
+    }
+
+    #[test]
+    fn write_events() {
+        let tracer = Tracer::new();
+        {
+            let _span = tracer.span("frame").arg("window", "\"main\"");
+            tracer.flow_start("frame", 7);
+        }
+        let mut output = Vec::new();
+        tracer.write(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("{\"displayTimeUnit\":\"ms\",\"traceEvents\":["));
+        assert!(output.trim_end().ends_with("]}"));
+        assert!(output.contains("\"ph\":\"M\""));
+        assert!(output.contains("\"ph\":\"s\",\"id\":7"));
+        assert!(output.contains("\"ph\":\"X\""));
+        assert!(output.contains("\"args\":{\"window\":\"\\\"main\\\"\"}"));
+    }
+
+    #[test]
+    fn disabled_tracer_writes_nothing() {
+        let tracer = Tracer::default();
+        drop(tracer.span("frame"));
+        let mut output = Vec::new();
+        tracer.write(&mut output).unwrap();
+        assert!(output.is_empty());
+    }
+}
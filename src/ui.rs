@@ -1,71 +1,810 @@
+use crate::animate;
+use crate::path;
 use crate::render;
+use crate::scene;
+use crate::shader;
 use crate::utils;
 use rayon::prelude::*;
+use std::collections::HashMap;
 
 pub struct QuadManager {
     pub quads: Vec<render::Quad>,
+    /// one animation per quad, empty as long as none of the quads is animated
+    animations: Vec<animate::Animation>,
+    /// quads added by each scene node, in drawing order
+    entries: Vec<QuadEntry>,
+    /// entry replaced by the next `add_quads` while patching, `None` while adding
+    cursor: Option<usize>,
+    draw_ranges: Vec<render::DrawRange>,
+    clip: Option<utils::ClipRect>,
+    transform: utils::Transform,
+}
+
+/// Quads added by one scene node. Their number never changes, so that they can be replaced in
+/// place when the node moves.
+struct QuadEntry {
+    start: u32,
+    end: u32,
+    clip: Option<utils::ClipRect>,
+    /// end of each run of backdrop panels or other quads among them
+    runs: Vec<(u32, bool)>,
 }
 
 impl QuadManager {
-    pub fn add_quad(&mut self, x: f32, y: f32, w: f32, h: f32, color: utils::Color) {
-        self.quads.push(render::Quad { x, y, w, h, color });
+    /// Add quads given in the current coordinate system, faded by `opacity`. While patching they
+    /// replace the quads of the current entry instead.
+    pub fn add_quads(
+        &mut self,
+        quads: &[render::Quad],
+        animations: Option<&[animate::Animation]>,
+        opacity: f32,
+    ) {
+        let transform = self.transform;
+        if let Some(cursor) = self.cursor.as_mut() {
+            let entry = &mut self.entries[*cursor];
+            *cursor += 1;
+            entry.clip = self.clip;
+            let range = entry.start as usize..entry.end as usize;
+            if let Some(animations) = animations {
+                self.animations[range.clone()]
+                    .par_iter_mut()
+                    .zip(animations.par_iter())
+                    .for_each(|(slot, animation)| *slot = animation.transformed(&transform));
+            }
+            self.quads[range]
+                .par_iter_mut()
+                .zip(quads.par_iter())
+                .for_each(|(slot, quad)| *slot = quad.transformed(&transform, opacity));
+            return;
+        }
+
+        let start = self.quads.len() as u32;
+        self.quads.par_extend(
+            quads
+                .par_iter()
+                .map(|quad| quad.transformed(&transform, opacity)),
+        );
+        // static quads next to animated ones stay in place
+        match animations {
+            Some(animations) => {
+                self.animations
+                    .resize(start as usize, animate::Animation::NONE);
+                self.animations.par_extend(
+                    animations
+                        .par_iter()
+                        .map(|animation| animation.transformed(&transform)),
+                );
+            }
+            None if !self.animations.is_empty() => {
+                self.animations
+                    .resize(self.quads.len(), animate::Animation::NONE);
+            }
+            None => {}
+        }
+
+        // backdrop panels get draw ranges of their own, so that they blur only what is drawn
+        // before them and what follows is drawn over them
+        let end = self.quads.len() as u32;
+        let runs = if quads.par_iter().any(render::Quad::is_backdrop_panel) {
+            let mut run_end = start;
+            quads
+                .chunk_by(|a, b| a.is_backdrop_panel() == b.is_backdrop_panel())
+                .map(|run| {
+                    run_end += run.len() as u32;
+                    (run_end, run[0].is_backdrop_panel())
+                })
+                .collect()
+        } else {
+            vec![(end, false)]
+        };
+        self.entries.push(QuadEntry {
+            start,
+            end,
+            clip: self.clip,
+            runs,
+        });
+    }
+
+    /// Keep the quads of the current entry while patching.
+    fn skip_quads(&mut self) {
+        if let Some(cursor) = self.cursor.as_mut() {
+            *cursor += 1;
+        }
+    }
+
+    fn begin(&mut self, patch: bool) {
+        if patch {
+            self.cursor = Some(0);
+            return;
+        }
+        self.quads.clear();
+        self.animations.clear();
+        self.entries.clear();
+    }
+
+    /// Rebuild the draw ranges from the entries.
+    fn finish(&mut self) {
+        self.cursor = None;
+        self.draw_ranges.clear();
+        for entry in self.entries.iter() {
+            let mut start = entry.start;
+            for &(end, backdrop) in entry.runs.iter() {
+                render::push_draw_range(&mut self.draw_ranges, start, end, entry.clip, backdrop);
+                start = end;
+            }
+        }
     }
 }
 
-pub struct UIState {
+/// Path added by a scene node, tessellated on its own so that changing it leaves the
+/// tessellation of the other paths alone.
+struct PathEntry {
+    path: lyon::path::Path,
+    style: path::PathStyle,
+    clip: Option<utils::ClipRect>,
+    /// `None` until the path is tessellated
+    mesh: Option<path::Mesh>,
+}
+
+pub struct PathManager {
+    /// paths added by each scene node, in drawing order
+    paths: Vec<PathEntry>,
+    /// entry replaced by the next `add_path` while patching, `None` while adding
+    cursor: Option<usize>,
+    mesh: path::Mesh,
+    draw_ranges: Vec<render::DrawRange>,
+    clip: Option<utils::ClipRect>,
+    transform: utils::Transform,
+    dirty: bool,
+}
+
+impl PathManager {
+    /// Add a path given in the current coordinate system, while patching it replaces the path of
+    /// the current entry instead.
+    pub fn add_path(&mut self, path: lyon::path::Path, style: path::PathStyle) {
+        let path = match self.transform {
+            utils::Transform::IDENTITY => path,
+            transform => path.transformed(&lyon::math::Transform::from(transform)),
+        };
+        let entry = PathEntry {
+            path,
+            style,
+            clip: self.clip,
+            mesh: None,
+        };
+        match self.cursor.as_mut() {
+            Some(cursor) => {
+                self.paths[*cursor] = entry;
+                *cursor += 1;
+            }
+            None => self.paths.push(entry),
+        }
+        self.dirty = true;
+    }
+
+    /// Keep the path of the current entry while patching.
+    fn skip_path(&mut self) {
+        if let Some(cursor) = self.cursor.as_mut() {
+            *cursor += 1;
+        }
+    }
+
+    /// Tessellate the paths that changed since the last call and put the meshes of all paths
+    /// together.
+    pub fn tessellate(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.mesh.vertices.clear();
+        self.mesh.indices.clear();
+        self.draw_ranges.clear();
+        for entry in self.paths.iter_mut() {
+            let mesh = entry.mesh.get_or_insert_with(|| {
+                let mut mesh = path::Mesh::new();
+                path::tessellate(&entry.path, &entry.style, &mut mesh);
+                mesh
+            });
+            let base = self.mesh.vertices.len() as u32;
+            let start = self.mesh.indices.len() as u32;
+            self.mesh.vertices.extend_from_slice(&mesh.vertices);
+            self.mesh
+                .indices
+                .extend(mesh.indices.iter().map(|index| base + index));
+            let end = self.mesh.indices.len() as u32;
+            render::push_draw_range(&mut self.draw_ranges, start, end, entry.clip, false);
+        }
+        self.dirty = false;
+    }
+
+    fn begin(&mut self, patch: bool) {
+        if patch {
+            self.cursor = Some(0);
+            return;
+        }
+        self.paths.clear();
+        self.dirty = true;
+    }
+
+    fn finish(&mut self) {
+        self.cursor = None;
+    }
+}
+
+/// Flat list of quads and paths in drawing order, built from the scene graph.
+pub struct DrawList {
     quad_manager: QuadManager,
+    path_manager: PathManager,
+    clip_stack: Vec<utils::ClipRect>,
+    transform_stack: Vec<utils::Transform>,
+    /// incremented whenever the contents change
+    version: u64,
+    /// whether anything was added since `begin`
+    modified: bool,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        let quad_manager = QuadManager {
+            quads: Vec::new(),
+            animations: Vec::new(),
+            entries: Vec::new(),
+            cursor: None,
+            draw_ranges: Vec::new(),
+            clip: None,
+            transform: utils::Transform::IDENTITY,
+        };
+        let path_manager = PathManager {
+            paths: Vec::new(),
+            cursor: None,
+            mesh: path::Mesh::new(),
+            draw_ranges: Vec::new(),
+            clip: None,
+            transform: utils::Transform::IDENTITY,
+            dirty: false,
+        };
+        DrawList {
+            quad_manager,
+            path_manager,
+            clip_stack: Vec::new(),
+            transform_stack: Vec::new(),
+            version: 0,
+            modified: false,
+        }
+    }
+
+    pub fn add_quads(
+        &mut self,
+        quads: &[render::Quad],
+        animations: Option<&[animate::Animation]>,
+        opacity: f32,
+    ) {
+        self.quad_manager.add_quads(quads, animations, opacity);
+        self.modified = true;
+    }
+
+    pub fn add_path(&mut self, path: lyon::path::Path, style: path::PathStyle) {
+        self.path_manager.add_path(path, style);
+        self.modified = true;
+    }
+
+    /// Keep the quads a scene node added in the previous pass, only while patching.
+    pub fn skip_quads(&mut self) {
+        self.quad_manager.skip_quads();
+    }
+
+    /// Keep the path a scene node added in the previous pass, only while patching.
+    pub fn skip_path(&mut self) {
+        self.path_manager.skip_path();
+    }
+
+    /// Start a pass over the scene graph. It either rebuilds the list from scratch, or with
+    /// `patch` visits the same nodes in the same order as the previous pass, where changed nodes
+    /// replace what they added before and the others skip it.
+    pub fn begin(&mut self, patch: bool) {
+        self.quad_manager.begin(patch);
+        self.path_manager.begin(patch);
+        self.modified = !patch;
+    }
+
+    pub fn finish(&mut self) {
+        self.quad_manager.finish();
+        self.path_manager.finish();
+        if self.modified {
+            self.version += 1;
+        }
+    }
+
+    /// Clip everything added from now on to the given rectangle in the current coordinate system,
+    /// intersected with the enclosing clips. Rotated clips are replaced by their bounding box.
+    pub fn push_clip(&mut self, rect: utils::ClipRect) {
+        let rect = rect.transformed(&self.quad_manager.transform);
+        let rect = match self.clip_stack.last() {
+            Some(parent) => parent.intersect(&rect),
+            None => rect,
+        };
+        self.clip_stack.push(rect);
+        self.set_clip(Some(rect));
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.clip_stack.pop();
+        self.set_clip(self.clip_stack.last().copied());
+    }
+
+    fn set_clip(&mut self, clip: Option<utils::ClipRect>) {
+        self.quad_manager.clip = clip;
+        self.path_manager.clip = clip;
+    }
+
+    /// Transform everything added from now on, relative to the enclosing transforms.
+    pub fn push_transform(&mut self, transform: utils::Transform) {
+        let transform = match self.transform_stack.last() {
+            Some(parent) => transform.then(parent),
+            None => transform,
+        };
+        self.transform_stack.push(transform);
+        self.set_transform(transform);
+    }
+
+    pub fn pop_transform(&mut self) {
+        self.transform_stack.pop();
+        self.set_transform(
+            self.transform_stack
+                .last()
+                .copied()
+                .unwrap_or(utils::Transform::IDENTITY),
+        );
+    }
+
+    fn set_transform(&mut self, transform: utils::Transform) {
+        self.quad_manager.transform = transform;
+        self.path_manager.transform = transform;
+    }
+}
+
+/// Independently uploaded part of the UI with its own scene graph, drawn on top of the layers
+/// added before it.
+pub struct Layer {
+    pub name: String,
+    pub blend: render::BlendMode,
+    /// view transform applied on the GPU, moving the camera does not re-upload the layer
+    pub camera: Option<utils::Transform>,
+    pub shader: shader::LayerShader,
+    pub scene: scene::Scene,
+    draw_list: DrawList,
+}
+
+impl Layer {
+    pub fn new(name: &str, blend: render::BlendMode) -> Self {
+        Layer {
+            name: name.to_string(),
+            blend,
+            camera: None,
+            shader: shader::LayerShader::default(),
+            scene: scene::Scene::new(),
+            draw_list: DrawList::new(),
+        }
+    }
+
+    /// Bring the draw list up to date with the scene graph and tessellate changed paths.
+    pub fn flatten(&mut self) {
+        self.scene.flatten(&mut self.draw_list);
+        self.draw_list.path_manager.tessellate();
+    }
+
+    /// Changes whenever the contents of the layer have to be uploaded again.
+    pub fn version(&self) -> u64 {
+        self.draw_list.version
+    }
+
+    pub fn quads(&self) -> &[render::Quad] {
+        &self.draw_list.quad_manager.quads
+    }
+
+    /// Animations of the quads, empty if the layer has no animated quads.
+    pub fn animations(&self) -> &[animate::Animation] {
+        &self.draw_list.quad_manager.animations
+    }
+
+    pub fn mesh(&self) -> &path::Mesh {
+        &self.draw_list.path_manager.mesh
+    }
+
+    pub fn quad_draw_ranges(&self) -> &[render::DrawRange] {
+        &self.draw_list.quad_manager.draw_ranges
+    }
+
+    pub fn mesh_draw_ranges(&self) -> &[render::DrawRange] {
+        &self.draw_list.path_manager.draw_ranges
+    }
+
+    pub fn has_backdrop_blur(&self) -> bool {
+        self.draw_list
+            .quad_manager
+            .draw_ranges
+            .iter()
+            .any(|range| range.backdrop)
+    }
+}
+
+/// Nodes of the example scene that are animated in `UIState::update`.
+#[derive(Clone, Copy)]
+struct Demo {
+    panel: scene::NodeId,
+    icons: scene::NodeId,
+    spinner: scene::NodeId,
+    spinner_transform: utils::Transform,
+}
+
+pub struct UIState {
+    layers: Vec<Layer>,
+    demo: Option<Demo>,
 }
 
 impl UIState {
     pub fn new() -> Self {
-        let quad_manager = QuadManager { quads: Vec::new() };
-        Self { quad_manager }
+        Self {
+            layers: Vec::new(),
+            demo: None,
+        }
+    }
+
+    /// Add a layer on top of the existing ones, returns its scene graph.
+    pub fn add_layer(&mut self, name: &str, blend: render::BlendMode) -> &mut scene::Scene {
+        self.layers.push(Layer::new(name, blend));
+        &mut self.layers.last_mut().unwrap().scene
+    }
+
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.name == name)
+    }
+
+    /// Use the custom shaders and uniforms registered for the layers by name.
+    pub fn apply_shaders(&mut self, shaders: &HashMap<String, shader::LayerShader>) {
+        for layer in self.layers.iter_mut() {
+            layer.shader = shaders.get(&layer.name).cloned().unwrap_or_default();
+        }
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Split into one state per layer, so that each layer can be constructed by its own worker.
+    pub fn split_layers(self) -> Vec<UIState> {
+        let demo = self.demo;
+        self.layers
+            .into_iter()
+            .map(|layer| UIState {
+                layers: vec![layer],
+                demo,
+            })
+            .collect()
     }
 
     pub fn setup(&mut self) {
+        // static checkerboard behind everything, only its camera is animated
+        let background = self.add_layer("background", render::BlendMode::Opaque);
+        let tiles = (0..12 * 12)
+            .map(|k| {
+                let (i, j) = (k % 12, k / 12);
+                let shade = match (i + j) % 2 {
+                    0 => 0.08,
+                    _ => 0.12,
+                };
+                render::Quad::new(
+                    -1.375 + i as f32 * 0.25,
+                    -1.375 + j as f32 * 0.25,
+                    0.25,
+                    0.25,
+                    utils::Color {
+                        r: shade,
+                        g: shade,
+                        b: shade,
+                        a: 1.0,
+                    },
+                )
+            })
+            .collect();
+        background.add_quads(scene::Scene::ROOT, tiles);
+
+        // millions of quads waving around their resting place, moved on the GPU and uploaded once
+        let grid = self.add_layer("grid", render::BlendMode::Alpha);
         let n = 2000;
         let quad_size = 0.001;
+        let mut grid_quads = Vec::with_capacity(n * n);
+        let mut grid_animations = Vec::with_capacity(n * n);
         for i in 0..n {
             for j in 0..n {
-                self.quad_manager.add_quad(
-                    (i as f32 / n as f32) - 0.5,
-                    (j as f32 / n as f32) - 0.5,
+                let (x, y) = (j as f32 / n as f32, i as f32 / n as f32);
+                grid_quads.push(render::Quad::new(
+                    x - 0.5,
+                    y - 0.5,
                     quad_size,
                     quad_size,
                     utils::Color {
-                        r: i as f32 / n as f32,
-                        g: j as f32 / n as f32,
-                        b: ((i as f32 / n as f32) * 2.0 - 1.0)
-                            * ((j as f32 / n as f32) * 2.0 - 1.0),
+                        r: y,
+                        g: x,
+                        b: (y * 2.0 - 1.0) * (x * 2.0 - 1.0),
                         a: 1.0,
                     },
+                ));
+                // offset by 0.4 * sin(t + 6x) along x and 0.4 * cos(t + 6y) along y
+                grid_animations.push(
+                    animate::Animation::keyframes(
+                        [-0.4, -0.4],
+                        [0.4, 0.4],
+                        std::f32::consts::PI,
+                        animate::Easing::Sine,
+                        animate::Repeat::PingPong,
+                    )
+                    .delayed(
+                        -(x * 6.0 + std::f32::consts::FRAC_PI_2),
+                        -(y * 6.0 + std::f32::consts::PI),
+                    ),
                 );
             }
         }
+        grid.add_animated_quads(scene::Scene::ROOT, grid_quads, grid_animations);
+
+        let content = self.add_layer("content", render::BlendMode::Alpha);
+
+        // frosted panel with a drop shadow on top of the grid, moved as a whole
+        let panel = content.add_group(scene::Scene::ROOT, utils::Transform::IDENTITY);
+        content.add_quads(
+            panel,
+            vec![
+                render::Quad::shadow(
+                    0.0,
+                    0.0,
+                    0.8,
+                    0.5,
+                    0.05,
+                    &render::Shadow {
+                        offset_x: 0.02,
+                        offset_y: -0.03,
+                        blur_radius: 0.08,
+                        spread: 0.0,
+                        color: utils::Color {
+                            r: 0.0,
+                            g: 0.0,
+                            b: 0.0,
+                            a: 0.6,
+                        },
+                    },
+                ),
+                render::Quad::backdrop_panel(
+                    0.0,
+                    0.0,
+                    0.8,
+                    0.5,
+                    utils::Color {
+                        r: 1.0,
+                        g: 1.0,
+                        b: 1.0,
+                        a: 0.15,
+                    },
+                )
+                .rounded(0.05),
+            ],
+        );
+
+        // a few vector icons on the panel, clipped to its bounds
+        let panel_content = content.add_group(panel, utils::Transform::IDENTITY);
+        content.set_clip(
+            panel_content,
+            Some(utils::ClipRect {
+                x: 0.0,
+                y: 0.0,
+                w: 0.8,
+                h: 0.5,
+            }),
+        );
+        let icons = content.add_group(panel_content, utils::Transform::IDENTITY);
+        let white = utils::Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            a: 0.9,
+        };
+        let mut star = path::PathBuilder::new();
+        for k in 0..5 {
+            let angle = std::f32::consts::FRAC_PI_2 + k as f32 * 4.0 * std::f32::consts::PI / 5.0;
+            star.line_to(-0.25 + angle.cos() * 0.1, angle.sin() * 0.1);
+        }
+        star.close();
+        content.add_path(
+            icons,
+            star.build(),
+            path::PathStyle {
+                fill: Some(path::Fill {
+                    color: white,
+                    rule: path::FillRule::EvenOdd,
+                }),
+                stroke: None,
+            },
+        );
+
+        let mut blob = path::PathBuilder::new();
+        blob.move_to(-0.05, 0.0)
+            .cubic_to(-0.05, 0.12, 0.05, 0.12, 0.05, 0.0)
+            .cubic_to(0.05, -0.08, -0.05, -0.12, -0.05, 0.0)
+            .close();
+        content.add_path(
+            icons,
+            blob.build(),
+            path::PathStyle {
+                fill: Some(path::Fill {
+                    color: white,
+                    rule: path::FillRule::NonZero,
+                }),
+                stroke: None,
+            },
+        );
+
+        let mut gauge = path::PathBuilder::new();
+        gauge
+            .arc(0.25, -0.02, 0.09, 0.09, -0.5, std::f32::consts::PI + 1.0)
+            .move_to(0.25, -0.02)
+            .quadratic_to(0.28, 0.02, 0.3, 0.06);
+        content.add_path(
+            icons,
+            gauge.build(),
+            path::PathStyle {
+                fill: None,
+                stroke: Some(path::Stroke {
+                    color: white,
+                    width: 0.01,
+                }),
+            },
+        );
+
+        // spinner made of rotated bars, placed and scaled as a group
+        let spinner_transform =
+            utils::Transform::scale(0.6, 0.6).then(&utils::Transform::translation(0.0, -0.16));
+        let spinner = content.add_group(panel_content, spinner_transform);
+        let bars = (0..12)
+            .map(|k| {
+                let angle = k as f32 * std::f32::consts::TAU / 12.0;
+                render::Quad::new(
+                    angle.cos() * 0.08,
+                    angle.sin() * 0.08,
+                    0.06,
+                    0.015,
+                    utils::Color {
+                        a: 0.3 + 0.7 * k as f32 / 12.0,
+                        ..white
+                    },
+                )
+                .rounded(0.0075)
+                .rotated(angle)
+            })
+            .collect();
+        content.add_quads(spinner, bars);
+
+        // easing curves side by side, each spinning dot crosses the panel every two seconds
+        let easings = [
+            animate::Easing::Linear,
+            animate::Easing::EaseIn,
+            animate::Easing::EaseOut,
+            animate::Easing::EaseInOut,
+        ];
+        let (dots, dot_animations) = easings
+            .iter()
+            .enumerate()
+            .map(|(k, easing)| {
+                let dot = render::Quad::new(-0.3, 0.22 - k as f32 * 0.03, 0.015, 0.015, white)
+                    .rounded(0.004);
+                let animation = animate::Animation::keyframes(
+                    [0.0, 0.0],
+                    [0.6, 0.0],
+                    2.0,
+                    *easing,
+                    animate::Repeat::Loop,
+                )
+                .spinning(std::f32::consts::PI);
+                (dot, animation)
+            })
+            .unzip();
+        content.add_animated_quads(panel_content, dots, dot_animations);
+
+        // soft light added on top of the content, circling at 0.7 radians per second
+        let overlay = self.add_layer("overlay", render::BlendMode::Additive);
+        let angular_velocity = 0.7;
+        overlay.add_animated_quads(
+            scene::Scene::ROOT,
+            vec![render::Quad {
+                blur: 0.2,
+                ..render::Quad::new(
+                    0.0,
+                    0.0,
+                    0.3,
+                    0.3,
+                    utils::Color {
+                        r: 0.3,
+                        g: 0.2,
+                        b: 0.05,
+                        a: 1.0,
+                    },
+                )
+                .rounded(0.15)
+            }],
+            vec![animate::Animation::keyframes(
+                [-0.6, -0.4],
+                [0.6, 0.4],
+                std::f32::consts::PI / angular_velocity,
+                animate::Easing::Sine,
+                animate::Repeat::PingPong,
+            )
+            .delayed(
+                -std::f32::consts::PI / angular_velocity,
+                -std::f32::consts::FRAC_PI_2 / angular_velocity,
+            )],
+        );
+
+        // outline of the grid's resting area, uploaded once
+        let debug = self.add_layer("debug", render::BlendMode::Alpha);
+        let mut outline = path::PathBuilder::new();
+        outline
+            .move_to(-0.5, -0.5)
+            .line_to(0.5, -0.5)
+            .line_to(0.5, 0.5)
+            .line_to(-0.5, 0.5)
+            .close();
+        debug.add_path(
+            scene::Scene::ROOT,
+            outline.build(),
+            path::PathStyle {
+                fill: None,
+                stroke: Some(path::Stroke {
+                    color: utils::Color {
+                        r: 0.2,
+                        g: 1.0,
+                        b: 0.4,
+                        a: 0.5,
+                    },
+                    width: 0.004,
+                }),
+            },
+        );
+
+        self.demo = Some(Demo {
+            panel,
+            icons,
+            spinner,
+            spinner_transform,
+        });
     }
 
     pub fn update(&mut self, start_time: std::time::Instant) {
+        let Some(demo) = self.demo else {
+            return;
+        };
         let delta = start_time.elapsed().as_secs_f32();
-        let n = (self.quad_manager.quads.len() as f32).sqrt();
 
-        self.quad_manager
-            .quads
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, quad)| {
-                let i = i as f32;
-                let x = i % n;
-                let y = i / n;
-                quad.x = (x / n) - 0.5 + (delta * 1.0 + x / n * 6.0).sin() * 0.4;
-                quad.y = (y / n) - 0.5 + (delta * 1.0 + y / n * 6.0).cos() * 0.4;
-            });
-    }
+        if let Some(background) = self.layer_mut("background") {
+            background.camera = Some(utils::Transform::rotation(delta * 0.05).then(
+                &utils::Transform::translation(0.0, (delta * 0.2).sin() * 0.1),
+            ));
+        }
 
-    pub fn quads(&self) -> &[f32] {
-        bytemuck::cast_slice(&self.quad_manager.quads)
+        if let Some(content) = self.layer_mut("content") {
+            let scene = &mut content.scene;
+            // moving the panel, spinning the spinner and blinking the icons are single node updates
+            scene.set_transform(
+                demo.panel,
+                utils::Transform::translation(0.0, (delta * 0.5).sin() * 0.05),
+            );
+            scene.set_transform(
+                demo.spinner,
+                utils::Transform::rotation(-delta * 3.0).then(&demo.spinner_transform),
+            );
+            scene.set_opacity(demo.spinner, 0.7 + 0.3 * (delta * 2.0).sin());
+            scene.set_visible(demo.icons, (delta as u32) % 4 != 3);
+        }
     }
 
-    pub fn num_quads(&self) -> u32 {
-        self.quad_manager.quads.len() as u32
+    pub fn flatten(&mut self) {
+        self.layers.par_iter_mut().for_each(Layer::flatten);
     }
 }
@@ -1,3 +1,4 @@
+use crate::construct;
 use bytemuck::NoUninit;
 use online_statistics::{self, stats::Univariate};
 
@@ -10,26 +11,458 @@ pub struct Color {
     pub a: f32,
 }
 
-#[derive(Default)]
+/// 2D affine transform mapping (x, y) to (a * x + c * y + tx, b * x + d * y + ty).
+#[derive(Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        a: 1.0,
+        b: 0.0,
+        c: 0.0,
+        d: 1.0,
+        tx: 0.0,
+        ty: 0.0,
+    };
+
+    pub fn translation(x: f32, y: f32) -> Transform {
+        Transform {
+            tx: x,
+            ty: y,
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// Counter-clockwise rotation around the origin, in radians.
+    pub fn rotation(angle: f32) -> Transform {
+        let (sin, cos) = angle.sin_cos();
+        Transform {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Transform {
+        Transform {
+            a: x,
+            d: y,
+            ..Transform::IDENTITY
+        }
+    }
+
+    /// Transform that applies `self` first and `other` afterwards.
+    pub fn then(&self, other: &Transform) -> Transform {
+        Transform {
+            a: other.a * self.a + other.c * self.b,
+            b: other.b * self.a + other.d * self.b,
+            c: other.a * self.c + other.c * self.d,
+            d: other.b * self.c + other.d * self.d,
+            tx: other.a * self.tx + other.c * self.ty + other.tx,
+            ty: other.b * self.tx + other.d * self.ty + other.ty,
+        }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.tx,
+            self.b * x + self.d * y + self.ty,
+        )
+    }
+
+    /// Linear part of the transform in column-major order, as used by the quad shader.
+    pub fn linear(&self) -> [f32; 4] {
+        [self.a, self.b, self.c, self.d]
+    }
+}
+
+impl From<Transform> for lyon::math::Transform {
+    fn from(transform: Transform) -> lyon::math::Transform {
+        lyon::math::Transform::new(
+            transform.a,
+            transform.b,
+            transform.c,
+            transform.d,
+            transform.tx,
+            transform.ty,
+        )
+    }
+}
+
+/// Axis-aligned rectangle given by its center and size.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ClipRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl ClipRect {
+    pub fn intersect(&self, other: &ClipRect) -> ClipRect {
+        let left = (self.x - self.w * 0.5).max(other.x - other.w * 0.5);
+        let right = (self.x + self.w * 0.5).min(other.x + other.w * 0.5);
+        let bottom = (self.y - self.h * 0.5).max(other.y - other.h * 0.5);
+        let top = (self.y + self.h * 0.5).min(other.y + other.h * 0.5);
+        ClipRect {
+            x: (left + right) * 0.5,
+            y: (bottom + top) * 0.5,
+            w: (right - left).max(0.0),
+            h: (top - bottom).max(0.0),
+        }
+    }
+
+    /// Bounding box of the rectangle after applying a transform.
+    pub fn transformed(&self, transform: &Transform) -> ClipRect {
+        let (x, y) = transform.apply(self.x, self.y);
+        let half_w = (transform.a.abs() * self.w + transform.c.abs() * self.h) * 0.5;
+        let half_h = (transform.b.abs() * self.w + transform.d.abs() * self.h) * 0.5;
+        ClipRect {
+            x,
+            y,
+            w: half_w * 2.0,
+            h: half_h * 2.0,
+        }
+    }
+
+    /// Convert to a scissor rectangle (x, y, width, height) in pixels of a target with the given size.
+    pub fn to_scissor(self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let to_pixels = |value: f32, size: u32| ((value * 0.5 + 0.5) * size as f32).round() as i64;
+        let left = to_pixels(self.x - self.w * 0.5, width).clamp(0, width as i64);
+        let right = to_pixels(self.x + self.w * 0.5, width).clamp(0, width as i64);
+        // pixel rows go from top to bottom
+        let top = to_pixels(-(self.y + self.h * 0.5), height).clamp(0, height as i64);
+        let bottom = to_pixels(-(self.y - self.h * 0.5), height).clamp(0, height as i64);
+        (
+            left as u32,
+            top as u32,
+            (right - left).max(0) as u32,
+            (bottom - top).max(0) as u32,
+        )
+    }
+}
+
+/// Upper bounds of the histogram buckets in seconds, the last one collects everything slower.
+pub const HISTOGRAM_BOUNDS: [f64; 8] = [
+    0.002,
+    0.004,
+    0.008,
+    0.0167,
+    0.0333,
+    0.05,
+    0.1,
+    f64::INFINITY,
+];
+/// Number of most recent values the histogram is computed over.
+const HISTOGRAM_WINDOW: usize = 600;
+
+/// Streaming statistics of a duration in seconds.
 pub struct Stats {
-    pub mean: online_statistics::mean::Mean<f64>,
-    pub variance: online_statistics::variance::Variance<f64>,
+    mean: online_statistics::mean::Mean<f64>,
+    variance: online_statistics::variance::Variance<f64>,
+    quantiles: [online_statistics::quantile::Quantile<f64>; 3],
+    max: online_statistics::maximum::Max<f64>,
+    count: u64,
+    recent: std::collections::VecDeque<f64>,
+    /// frames that were dropped, what counts as dropped depends on what is measured
+    pub dropped: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        let quantile = |q| online_statistics::quantile::Quantile::new(q).unwrap();
+        Stats {
+            mean: Default::default(),
+            variance: Default::default(),
+            quantiles: [quantile(0.5), quantile(0.95), quantile(0.99)],
+            max: Default::default(),
+            count: 0,
+            recent: std::collections::VecDeque::with_capacity(HISTOGRAM_WINDOW),
+            dropped: 0,
+        }
+    }
 }
 
 impl Stats {
     pub fn update(&mut self, value: f64) {
         self.mean.update(value);
         self.variance.update(value);
+        for quantile in self.quantiles.iter_mut() {
+            quantile.update(value);
+        }
+        self.max.update(value);
+        self.count += 1;
+        if self.recent.len() == HISTOGRAM_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(value);
+    }
+
+    /// Record the time between two frames, counting it as dropped if it exceeds the expected
+    /// interval by half.
+    pub fn update_interval(&mut self, interval: f64, expected: f64) {
+        self.update(interval);
+        if interval > 1.5 * expected {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean.get()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance.get().sqrt()
+    }
+
+    /// Estimated 50th, 95th and 99th percentiles, zero before the first value.
+    pub fn percentiles(&self) -> [f64; 3] {
+        match self.count {
+            0 => [0.0; 3],
+            _ => self.quantiles.each_ref().map(|quantile| quantile.get()),
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max.get()
+    }
+
+    /// Number of recent values in each bucket of `HISTOGRAM_BOUNDS`.
+    pub fn histogram(&self) -> [u64; HISTOGRAM_BOUNDS.len()] {
+        let mut histogram = [0; HISTOGRAM_BOUNDS.len()];
+        for &value in self.recent.iter() {
+            let bucket = HISTOGRAM_BOUNDS
+                .iter()
+                .position(|&bound| value <= bound)
+                .unwrap_or(HISTOGRAM_BOUNDS.len() - 1);
+            histogram[bucket] += 1;
+        }
+        histogram
     }
 }
 
 impl std::fmt::Display for Stats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [_, _, p99] = self.percentiles();
         write!(
             f,
-            "μ = {:>5.2}ms ± {:>5.2}ms",
-            self.mean.get() * 1000.0,
-            self.variance.get().sqrt() * 1000.0,
+            "μ = {:>5.2}ms ± {:>5.2}ms, p99 = {:>5.2}ms, max = {:>5.2}ms, {} dropped",
+            self.mean() * 1000.0,
+            self.std_dev() * 1000.0,
+            p99 * 1000.0,
+            self.max() * 1000.0,
+            self.dropped,
         )
     }
 }
+
+/// Timing statistics of a window, shared by the render thread, the construction worker and Python.
+#[derive(Default)]
+pub struct WindowStats {
+    /// time between presented frames, dropped when it exceeds the display refresh interval by half
+    pub frame: Stats,
+    /// time spent by the render thread on a frame
+    pub render: Stats,
+    /// time spent by the worker constructing a frame, dropped when the render thread never got it
+    pub construct: Stats,
+    /// GPU time of copying the staging buffers into the storage buffers
+    pub gpu_copy: Stats,
+    /// GPU time of the render passes of a frame
+    pub gpu_render: Stats,
+    /// time from an input event until the first frame constructed after it is presented
+    pub latency: Stats,
+    /// buffer pools of the construction workers as of their last constructed frame
+    pub pools: Vec<construct::PoolStats>,
+}
+
+pub type SharedStats = std::sync::Arc<std::sync::Mutex<WindowStats>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> ClipRect {
+        ClipRect { x, y, w, h }
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-5 && (actual.1 - expected.1).abs() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let scale = Transform::scale(2.0, 3.0);
+        let translation = Transform::translation(1.0, -1.0);
+        assert_near(scale.then(&translation).apply(1.0, 1.0), (3.0, 2.0));
+        assert_near(translation.then(&scale).apply(1.0, 1.0), (4.0, 0.0));
+    }
+
+    #[test]
+    fn then_matches_applying_in_turn() {
+        let first = Transform::rotation(0.3).then(&Transform::translation(0.5, 2.0));
+        let second = Transform::scale(-1.5, 0.5).then(&Transform::rotation(-1.2));
+        let combined = first.then(&second);
+        for (x, y) in [(0.0, 0.0), (1.0, 0.0), (-2.0, 3.5)] {
+            let (fx, fy) = first.apply(x, y);
+            assert_near(combined.apply(x, y), second.apply(fx, fy));
+        }
+    }
+
+    #[test]
+    fn then_identity() {
+        let transform = Transform::rotation(0.7).then(&Transform::translation(1.0, 2.0));
+        assert!(transform.then(&Transform::IDENTITY) == transform);
+        assert!(Transform::IDENTITY.then(&transform) == transform);
+    }
+
+    #[test]
+    fn intersect_overlapping() {
+        let clip = rect(0.0, 0.0, 2.0, 2.0).intersect(&rect(1.0, 0.5, 2.0, 1.0));
+        assert!(clip == rect(0.5, 0.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn intersect_disjoint_is_empty() {
+        let clip = rect(-1.0, 0.0, 1.0, 1.0).intersect(&rect(1.0, 0.0, 1.0, 1.0));
+        assert_eq!((clip.w, clip.h), (0.0, 1.0));
+    }
+
+    #[test]
+    fn transformed_bounds() {
+        let translated = rect(0.0, 0.0, 2.0, 1.0).transformed(&Transform::translation(1.0, 2.0));
+        assert!(translated == rect(1.0, 2.0, 2.0, 1.0));
+        let scaled = rect(1.0, 1.0, 2.0, 1.0).transformed(&Transform::scale(2.0, -1.0));
+        assert!(scaled == rect(2.0, -1.0, 4.0, 1.0));
+        // a quarter turn swaps width and height
+        let rotated =
+            rect(0.0, 0.0, 2.0, 1.0).transformed(&Transform::rotation(std::f32::consts::FRAC_PI_2));
+        assert!((rotated.w - 1.0).abs() < 1e-6 && (rotated.h - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn scissor_of_viewport() {
+        assert_eq!(
+            rect(0.0, 0.0, 2.0, 2.0).to_scissor(800, 600),
+            (0, 0, 800, 600)
+        );
+        // the upper left quarter starts at the first pixel row
+        assert_eq!(
+            rect(-0.5, 0.5, 1.0, 1.0).to_scissor(800, 600),
+            (0, 0, 400, 300)
+        );
+        assert_eq!(
+            rect(0.5, -0.5, 1.0, 1.0).to_scissor(800, 600),
+            (400, 300, 400, 300)
+        );
+    }
+
+    #[test]
+    fn scissor_clamped_to_target() {
+        assert_eq!(
+            rect(1.0, 1.0, 2.0, 2.0).to_scissor(800, 600),
+            (400, 0, 400, 300)
+        );
+        assert_eq!(
+            rect(0.0, 0.0, 4.0, 4.0).to_scissor(800, 600),
+            (0, 0, 800, 600)
+        );
+        // outside of the target on either side
+        let (_, _, w, h) = rect(3.0, 0.0, 1.0, 1.0).to_scissor(800, 600);
+        assert_eq!((w, h), (0, 300));
+        let (_, _, w, h) = rect(0.0, -3.0, 1.0, 1.0).to_scissor(800, 600);
+        assert_eq!((w, h), (400, 0));
+    }
+
+    #[test]
+    fn scissor_of_empty_rect() {
+        let (_, _, w, h) = rect(0.0, 0.0, 0.0, 0.0).to_scissor(800, 600);
+        assert_eq!((w, h), (0, 0));
+    }
+
+    #[test]
+    fn stats_start_empty() {
+        let stats = Stats::default();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.percentiles(), [0.0; 3]);
+        assert_eq!(stats.histogram(), [0; HISTOGRAM_BOUNDS.len()]);
+    }
+
+    #[test]
+    fn stats_summary() {
+        let mut stats = Stats::default();
+        for value in [0.01, 0.02, 0.03] {
+            stats.update(value);
+        }
+        assert_eq!(stats.count(), 3);
+        assert!((stats.mean() - 0.02).abs() < 1e-9);
+        assert!((stats.max() - 0.03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn stats_percentiles() {
+        let mut stats = Stats::default();
+        for i in 1..=1000 {
+            stats.update(i as f64 / 1000.0);
+        }
+        let [p50, p95, p99] = stats.percentiles();
+        assert!((p50 - 0.5).abs() < 0.05, "p50 = {}", p50);
+        assert!((p95 - 0.95).abs() < 0.05, "p95 = {}", p95);
+        assert!((p99 - 0.99).abs() < 0.05, "p99 = {}", p99);
+    }
+
+    #[test]
+    fn dropped_intervals() {
+        let mut stats = Stats::default();
+        for interval in [0.016, 0.024, 0.026, 0.05] {
+            stats.update_interval(interval, 1.0 / 60.0);
+        }
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.dropped, 2);
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        let mut stats = Stats::default();
+        // on a bound, between two bounds and slower than the last finite bound
+        for value in [0.002, 0.003, 0.016, 0.5] {
+            stats.update(value);
+        }
+        assert_eq!(stats.histogram(), [1, 1, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn histogram_keeps_recent_values() {
+        let mut stats = Stats::default();
+        for _ in 0..HISTOGRAM_WINDOW {
+            stats.update(1.0);
+        }
+        for _ in 0..10 {
+            stats.update(0.001);
+        }
+        let histogram = stats.histogram();
+        assert_eq!(histogram[0], 10);
+        assert_eq!(
+            histogram[HISTOGRAM_BOUNDS.len() - 1],
+            HISTOGRAM_WINDOW as u64 - 10
+        );
+        assert_eq!(stats.count(), HISTOGRAM_WINDOW as u64 + 10);
+    }
+}
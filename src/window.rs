@@ -1,79 +1,382 @@
+use crate::adapter;
 use crate::construct;
+use crate::error::{Error, Result};
+use crate::render;
 use crate::render::Renderer;
+use crate::shader;
+use crate::trace;
 use crate::utils;
 use pollster::FutureExt;
-use std::sync::Arc;
-use wgpu;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{atomic, Arc, Mutex};
 use winit::application::ApplicationHandler;
-use winit::event::WindowEvent;
-use winit::window::Window;
+use winit::event::{StartCause, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoopProxy};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{Window, WindowId};
+
+/// Number of times in a row recreating the renderer after a device loss is tried before giving up.
+const MAX_RECOVERY_ATTEMPTS: u32 = 10;
+const RECOVERY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Memory the buffer pool of a window may take up unless configured otherwise.
+pub const DEFAULT_POOL_BUDGET: u64 = 512 * 1024 * 1024;
+
+/// Called with the reason when the GPU device is lost, before the renderer is recreated.
+pub type DeviceLostCallback = Box<dyn FnMut(&str) -> Result<()> + Send>;
+/// Called when the user closes the window.
+pub type CloseCallback = Box<dyn FnMut() -> Result<()> + Send>;
+
+pub struct Settings {
+    pub title: String,
+    /// number of samples per pixel for multisample anti-aliasing (1, 2, 4 or 8)
+    pub msaa_samples: u32,
+    /// all windows share one device, so all of them have to ask for the same adapter
+    pub adapter: adapter::AdapterSettings,
+    pub shaders: shader::ShaderSettings,
+    /// whether the statistics overlay is shown, toggled with F3
+    pub hud: Arc<atomic::AtomicBool>,
+    pub stats: utils::SharedStats,
+    /// spans of the render thread and the worker, shared by all windows of a trace
+    pub trace: trace::Tracer,
+    /// bytes the buffer pool of each construction worker may take up, `None` for no limit
+    pub pool_budget: Option<u64>,
+    /// whether the layers are constructed by one worker or a worker each
+    pub workers: construct::WorkerMode,
+    pub pacing: construct::Pacing,
+    /// set by input events, taken by the worker that constructs the next frame
+    pub pending_input: construct::PendingInput,
+    /// wakes up the workers when the scene may have changed
+    pub changes: construct::SceneChanges,
+    pub on_device_lost: Option<DeviceLostCallback>,
+    pub on_close: Option<CloseCallback>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            title: "guilible".to_string(),
+            msaa_samples: 1,
+            adapter: adapter::AdapterSettings::default(),
+            shaders: shader::ShaderSettings::default(),
+            hud: Arc::default(),
+            stats: utils::SharedStats::default(),
+            trace: trace::Tracer::default(),
+            pool_budget: Some(DEFAULT_POOL_BUDGET),
+            workers: construct::WorkerMode::Window,
+            pacing: construct::Pacing::Mailbox,
+            pending_input: construct::PendingInput::default(),
+            changes: construct::SceneChanges::default(),
+            on_device_lost: None,
+            on_close: None,
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct Application<'win> {
-    state: Option<State<'win>>,
+    windows: Vec<WindowSlot<'win>>,
+    /// woken by the construction workers with the window they sent a frame for
+    proxy: EventLoopProxy<WindowId>,
+    gpu: Option<Gpu>,
+    error: Option<Error>,
+    /// attempts to recreate the device so far, `None` unless the device was lost
+    recovery_attempts: Option<u32>,
+}
+
+/// A window of the application, it has no state before the device is created.
+struct WindowSlot<'win> {
+    settings: Settings,
     window: Option<Arc<Window>>,
+    state: Option<State<'win>>,
+    /// scenes of the window's workers while the device is recreated
+    scenes: Vec<construct::WorkerScene>,
+}
+
+impl<'win> Application<'win> {
+    pub fn new(settings: Vec<Settings>, proxy: EventLoopProxy<WindowId>) -> Self {
+        Application {
+            proxy,
+            windows: settings
+                .into_iter()
+                .map(|settings| WindowSlot {
+                    settings,
+                    window: None,
+                    state: None,
+                    scenes: Vec::new(),
+                })
+                .collect(),
+            gpu: None,
+            error: None,
+            recovery_attempts: None,
+        }
+    }
+
+    /// Error that made the event loop exit, if any.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    fn window_index(&self, window_id: WindowId) -> Option<usize> {
+        self.windows.iter().position(|slot| {
+            slot.window
+                .as_ref()
+                .is_some_and(|window| window.id() == window_id)
+        })
+    }
+
+    /// Stop the renderers, close all windows and exit the event loop, keeping the first error.
+    fn exit(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, error: Option<Error>) {
+        if let Some(e) = error {
+            self.error.get_or_insert(e);
+        }
+        for slot in self.windows.drain(..) {
+            if let Some(state) = slot.state {
+                if let Err(e) = state.stop() {
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+        self.recovery_attempts.take();
+        self.gpu.take();
+        event_loop.exit();
+    }
+
+    /// Close a single window, the event loop exits once the last one is closed.
+    fn close_window(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, index: usize) {
+        let mut slot = self.windows.remove(index);
+        log::info!("closing {}", slot.settings.title);
+        let mut result = slot.state.take().map_or(Ok(()), State::stop);
+        if let Some(callback) = slot.settings.on_close.as_mut() {
+            result = result.and(callback());
+        }
+        match result {
+            Err(e) => self.exit(event_loop, Some(e)),
+            Ok(()) if self.windows.is_empty() => self.exit(event_loop, None),
+            Ok(()) => {}
+        }
+    }
+
+    /// Create the device if there is none and a renderer for every window that lacks one.
+    fn create_states(&mut self) -> Result<()> {
+        for slot in self.windows.iter_mut() {
+            let (Some(window), None) = (&slot.window, &slot.state) else {
+                continue;
+            };
+            let surface = match &self.gpu {
+                Some(gpu) => gpu
+                    .instance
+                    .create_surface(window.clone())
+                    .map_err(Error::CreateSurface)?,
+                None => {
+                    let (gpu, surface) = Gpu::new(window.clone(), &slot.settings.adapter)?;
+                    self.gpu = Some(gpu);
+                    surface
+                }
+            };
+            let gpu = self.gpu.as_mut().unwrap();
+
+            let proxy = self.proxy.clone();
+            let window_id = window.id();
+            let wake: construct::Waker = Arc::new(move || {
+                // fails once the event loop has exited, there is nothing left to redraw then
+                let _ = proxy.send_event(window_id);
+            });
+            let state = State::new(
+                gpu,
+                window.clone(),
+                surface,
+                &slot.settings,
+                &mut slot.scenes,
+                wake,
+            )?;
+            slot.state = Some(state);
+        }
+        Ok(())
+    }
+
+    /// Tear down the renderers of a lost device and start recreating them with the same scenes.
+    fn device_lost(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, reason: String) {
+        log::warn!("GPU device lost ({}), recreating the renderer", reason);
+        let result = self
+            .windows
+            .iter_mut()
+            .filter_map(|slot| slot.settings.on_device_lost.as_mut())
+            .try_for_each(|callback| callback(&reason));
+        if let Err(e) = result {
+            return self.exit(event_loop, Some(e));
+        }
+        for slot in self.windows.iter_mut() {
+            if let Some(state) = slot.state.take() {
+                slot.scenes = state.tear_down();
+            }
+        }
+        // the surfaces are gone with the states, the device can be released as well
+        self.gpu = None;
+        self.recovery_attempts = Some(0);
+        self.recover(event_loop);
+    }
+
+    /// Try to recreate the renderers, schedules another attempt if the GPU is not available yet.
+    fn recover(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let Some(attempts) = self.recovery_attempts.as_mut() else {
+            return;
+        };
+        *attempts += 1;
+        let attempts = *attempts;
+        match self.create_states() {
+            Ok(()) => {
+                log::info!("recreated the renderer after {} attempt(s)", attempts);
+                self.recovery_attempts = None;
+                for window in self.windows.iter().filter_map(|slot| slot.window.as_ref()) {
+                    window.request_redraw();
+                }
+            }
+            Err(e) if attempts < MAX_RECOVERY_ATTEMPTS => {
+                log::warn!("failed to recreate the renderer: {}, retrying", e);
+                event_loop.set_control_flow(ControlFlow::WaitUntil(
+                    std::time::Instant::now() + RECOVERY_DELAY,
+                ));
+            }
+            Err(e) => {
+                log::error!("{}, exiting", e);
+                self.exit(event_loop, Some(e));
+            }
+        }
+    }
 }
 
-impl<'win> ApplicationHandler for Application<'win> {
+impl<'win> ApplicationHandler<WindowId> for Application<'win> {
+    fn new_events(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, cause: StartCause) {
+        // retry recreating the renderer once the recovery delay has passed
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            event_loop.set_control_flow(ControlFlow::Wait);
+            self.recover(event_loop);
+        }
+    }
+
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        println!("starting guilible");
-        println!("├─ creating window");
+        log::info!("starting guilible");
+
+        for slot in self.windows.iter_mut().filter(|slot| slot.window.is_none()) {
+            log::debug!("creating window {}", slot.settings.title);
+            let attributes = Window::default_attributes().with_title(&slot.settings.title);
+            match event_loop.create_window(attributes) {
+                Ok(window) => slot.window = Some(Arc::new(window)),
+                Err(e) => return self.exit(event_loop, Some(Error::Window(e))),
+            }
+        }
+        if let Err(e) = self.create_states() {
+            self.exit(event_loop, Some(e));
+        }
+    }
 
-        let win_arc = Arc::new(
-            event_loop
-                .create_window(Window::default_attributes())
-                .unwrap(),
-        );
-        self.window = Some(win_arc.clone());
-        self.state = Some(State::new(win_arc.clone()));
+    /// A construction worker sent a frame for the window or stopped.
+    fn user_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        window_id: WindowId,
+    ) {
+        if let Some(window) = self
+            .window_index(window_id)
+            .and_then(|index| self.windows[index].window.as_ref())
+        {
+            window.request_redraw();
+        }
     }
 
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        window_id: WindowId,
         event: winit::event::WindowEvent,
     ) {
+        let Some(index) = self.window_index(window_id) else {
+            return;
+        };
         match event {
             WindowEvent::CloseRequested => {
-                println!("\nclosing guilible");
+                self.close_window(event_loop, index);
+            }
 
-                // stop the renderer and drop the state
-                if let Some(state) = self.state.take() {
-                    state.renderer.stop_and_join();
-                    println!("╰─ render    : {}", state.stats);
-                }
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.logical_key == Key::Named(NamedKey::F3) =>
+            {
+                self.windows[index]
+                    .settings
+                    .hud
+                    .fetch_xor(true, atomic::Ordering::SeqCst);
+            }
 
-                // drop the window and exit the event loop
-                self.window.take();
-                event_loop.exit();
+            WindowEvent::KeyboardInput { .. }
+            | WindowEvent::MouseInput { .. }
+            | WindowEvent::MouseWheel { .. }
+            | WindowEvent::CursorMoved { .. } => {
+                // the earliest input counts until a frame reflects it
+                let settings = &self.windows[index].settings;
+                settings
+                    .pending_input
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(std::time::Instant::now);
+                settings.changes.notify();
             }
 
             WindowEvent::Resized(size) => {
-                if let Some(render_state) = self.state.as_mut() {
+                if let Some(render_state) = self.windows[index].state.as_mut() {
                     render_state.resize(Some(size));
+                    // draw the last frame at the new size without waiting for the workers
+                    render_state.window.request_redraw();
                 }
             }
 
             WindowEvent::RedrawRequested => {
-                // Request next frame
-                self.window.as_ref().unwrap().request_redraw();
+                let device_lost = self
+                    .gpu
+                    .as_ref()
+                    .and_then(|gpu| gpu.device_lost.lock().unwrap().take());
+                if let Some(reason) = device_lost {
+                    return self.device_lost(event_loop, reason);
+                }
+                // nothing to draw while the renderer is being recreated
+                let Some(render_state) = self.windows[index].state.as_mut() else {
+                    return;
+                };
+
+                // keep redrawing every refresh, otherwise the workers request the next frame
+                if render_state.pacing == construct::Pacing::ReuseLast {
+                    render_state.window.request_redraw();
+                }
 
                 // Render the frame
-                if let Some(render_state) = self.state.as_mut() {
-                    match render_state.render() {
-                        Ok(_) => {}
-                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                            println!("surface lost or outdated, recomfiguring");
-                            render_state.resize(None);
-                        }
-                        Err(wgpu::SurfaceError::OutOfMemory | wgpu::SurfaceError::Other) => {
-                            println!("surface error, exiting");
-                            event_loop.exit();
-                        }
-                        Err(wgpu::SurfaceError::Timeout) => {
-                            println!("surface timeout");
-                        }
+                match render_state.render() {
+                    // animated quads only move while the window is redrawn
+                    Ok(_) if render_state.renderer.is_animated() => {
+                        render_state.window.request_redraw();
+                    }
+                    Ok(_) => {}
+                    Err(Error::Surface(
+                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                    )) => {
+                        log::warn!("surface lost or outdated, reconfiguring");
+                        render_state.resize(None);
+                    }
+                    Err(Error::Surface(wgpu::SurfaceError::Timeout)) => {
+                        log::warn!("surface timeout");
+                    }
+                    Err(
+                        e @ (Error::Surface(
+                            wgpu::SurfaceError::OutOfMemory | wgpu::SurfaceError::Other,
+                        )
+                        | Error::BufferMap(_)),
+                    ) => {
+                        self.device_lost(event_loop, e.to_string());
+                    }
+                    Err(e) => {
+                        log::error!("{}, exiting", e);
+                        self.exit(event_loop, Some(e));
                     }
                 }
             }
@@ -82,63 +385,157 @@ impl<'win> ApplicationHandler for Application<'win> {
     }
 }
 
-struct State<'win> {
-    surface: wgpu::Surface<'win>,
-    config: wgpu::SurfaceConfiguration,
+/// Device shared by all windows, together with the pipelines created for them.
+struct Gpu {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     device_arc: Arc<wgpu::Device>,
     queue_arc: Arc<wgpu::Queue>,
-    window: Arc<Window>,
-
-    last_render_time: Option<std::time::Instant>,
-    stats: utils::Stats,
-
-    renderer: Renderer,
+    /// set with the reason once the device is lost
+    device_lost: Arc<Mutex<Option<String>>>,
+    pipelines: Vec<Rc<RefCell<render::Pipelines>>>,
 }
 
-impl<'win> State<'win> {
-    fn new(window: Arc<Window>) -> Self {
-        let window_size = window.inner_size();
-
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
-            ..Default::default()
-        });
+impl Gpu {
+    /// Create a device that can present to `window`, returns it with the window's surface.
+    fn new(
+        window: Arc<Window>,
+        settings: &adapter::AdapterSettings,
+    ) -> Result<(Gpu, wgpu::Surface<'static>)> {
+        let instance = adapter::create_instance(settings.backends);
 
         let surface = instance
-            .create_surface(window.clone())
-            .expect("failed to create surface");
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .block_on()
-            .expect("failed to find an adapter");
+            .create_surface(window)
+            .map_err(Error::CreateSurface)?;
+
+        let adapter = adapter::request_adapter(&instance, settings, &surface)?;
+        let info = adapter.get_info();
+        log::info!("using {} ({})", info.name, info.backend);
 
         let (_device, _queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::default(),
+                    // timestamps, culling and MSAA beyond 4x are only used when the adapter
+                    // supports them
+                    required_features: adapter.features()
+                        & (wgpu::Features::TIMESTAMP_QUERY
+                            | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
+                            | wgpu::Features::INDIRECT_FIRST_INSTANCE
+                            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES),
+                    // large layers need the full buffer sizes of the adapter
+                    required_limits: adapter.limits(),
                     label: None,
                     memory_hints: wgpu::MemoryHints::default(),
                 },
                 None,
             )
             .block_on()
-            .expect("failed to create device");
+            .map_err(Error::Device)?;
 
         let device_arc = Arc::new(_device);
         let queue_arc = Arc::new(_queue);
 
-        let surface_formats = surface.get_capabilities(&adapter).formats;
+        let device_lost = Arc::new(Mutex::new(None));
+        device_arc.set_device_lost_callback({
+            let device_lost = device_lost.clone();
+            move |reason, message| {
+                device_lost
+                    .lock()
+                    .unwrap()
+                    .get_or_insert(format!("{:?}: {}", reason, message));
+            }
+        });
+        device_arc.on_uncaptured_error(Box::new({
+            let device_lost = device_lost.clone();
+            move |error| match error {
+                wgpu::Error::OutOfMemory { .. } => {
+                    device_lost.lock().unwrap().get_or_insert(error.to_string());
+                }
+                // the default handler panics, which would take down the window when a lost
+                // device fails validation before the loss has been noticed
+                error => log::error!("{}", error),
+            }
+        }));
+
+        let gpu = Gpu {
+            instance,
+            adapter,
+            device_arc,
+            queue_arc,
+            device_lost,
+            pipelines: Vec::new(),
+        };
+        Ok((gpu, surface))
+    }
+
+    /// Pipelines for the given output, shared by all windows that render the same way.
+    fn pipelines(
+        &mut self,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        shader_dir: Option<&std::path::PathBuf>,
+    ) -> Rc<RefCell<render::Pipelines>> {
+        if let Some(pipelines) = self
+            .pipelines
+            .iter()
+            .find(|pipelines| pipelines.borrow().matches(format, sample_count, shader_dir))
+        {
+            return pipelines.clone();
+        }
+        let pipelines = Rc::new(RefCell::new(render::Pipelines::new(
+            self.device_arc.clone(),
+            format,
+            sample_count,
+            shader_dir.cloned(),
+        )));
+        self.pipelines.push(pipelines.clone());
+        pipelines
+    }
+}
+
+struct State<'win> {
+    surface: wgpu::Surface<'win>,
+    config: wgpu::SurfaceConfiguration,
+    sample_count: u32,
+    msaa_view: Option<wgpu::TextureView>,
+    device_arc: Arc<wgpu::Device>,
+    queue_arc: Arc<wgpu::Queue>,
+    window: Arc<Window>,
+    title: String,
+    hud: Arc<atomic::AtomicBool>,
+    trace: trace::Tracer,
+    pacing: construct::Pacing,
+
+    last_render_time: Option<std::time::Instant>,
+    /// seconds between refreshes of the display the window was opened on
+    refresh_interval: f64,
+    stats: utils::SharedStats,
+
+    renderer: Renderer,
+}
+
+impl<'win> State<'win> {
+    /// Create the renderer for a window, continuing with `scenes` if there are any.
+    fn new(
+        gpu: &mut Gpu,
+        window: Arc<Window>,
+        surface: wgpu::Surface<'win>,
+        settings: &Settings,
+        scenes: &mut Vec<construct::WorkerScene>,
+        wake: construct::Waker,
+    ) -> Result<Self> {
+        let window_size = window.inner_size();
+
+        if !gpu.adapter.is_surface_supported(&surface) {
+            return Err(Error::UnsupportedSurface);
+        }
+        let surface_formats = surface.get_capabilities(&gpu.adapter).formats;
         let surface_format = surface_formats
             .iter()
             .find(|format| format.is_srgb())
+            .or(surface_formats.first())
             .copied()
-            .unwrap_or(surface_formats[0]);
+            .ok_or(Error::UnsupportedSurface)?;
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -149,30 +546,98 @@ impl<'win> State<'win> {
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
-        surface.configure(&device_arc, &config);
-
-        let renderer = Renderer::new(device_arc.clone(), queue_arc.clone(), config.format);
+        surface.configure(&gpu.device_arc, &config);
 
-        println!("╰─ ready");
+        let sample_count = supported_sample_count(gpu, config.format, settings.msaa_samples);
+        let msaa_view = create_msaa_view(&gpu.device_arc, &config, sample_count);
 
-        Self {
+        let pipelines = gpu.pipelines(
+            config.format,
+            sample_count,
+            settings.shaders.shader_dir.as_ref(),
+        );
+        let renderer = Renderer::new(
+            gpu.device_arc.clone(),
+            gpu.queue_arc.clone(),
+            pipelines,
+            (config.width, config.height),
+            settings,
+            scenes,
+            wake,
+        )?;
+
+        let refresh_interval = window
+            .current_monitor()
+            .and_then(|monitor| monitor.refresh_rate_millihertz())
+            .map_or(1.0 / 60.0, |millihertz| 1000.0 / millihertz as f64);
+
+        log::info!("{} ready", settings.title);
+
+        Ok(Self {
             surface,
             config,
-            device_arc,
-            queue_arc,
+            sample_count,
+            msaa_view,
+            device_arc: gpu.device_arc.clone(),
+            queue_arc: gpu.queue_arc.clone(),
             window,
+            title: settings.title.clone(),
+            hud: settings.hud.clone(),
+            trace: settings.trace.clone(),
+            pacing: settings.pacing,
             last_render_time: None,
-            stats: utils::Stats::default(),
+            refresh_interval,
+            stats: settings.stats.clone(),
             renderer,
+        })
+    }
+
+    /// Stop the renderer of a window that is being closed.
+    fn stop(self) -> Result<()> {
+        let (_, result) = self.renderer.stop_and_join();
+        // format under the lock but log after releasing it, logging may take the GIL, which
+        // Python holds while it waits for the lock
+        let lines = {
+            let stats = self.stats.lock().unwrap();
+            let mut lines = vec![
+                ("frame", stats.frame.to_string()),
+                ("render", stats.render.to_string()),
+                ("construct", stats.construct.to_string()),
+            ];
+            if stats.gpu_copy.count() > 0 {
+                lines.push(("gpu copy", stats.gpu_copy.to_string()));
+            }
+            if stats.gpu_render.count() > 0 {
+                lines.push(("gpu render", stats.gpu_render.to_string()));
+            }
+            lines
+        };
+        for (name, line) in lines {
+            log::info!("{} ({}): {}", name, self.title, line);
+        }
+        result
+    }
+
+    /// Stop the renderer and release the device, keeping what is needed to recreate it.
+    fn tear_down(self) -> Vec<construct::WorkerScene> {
+        let (scenes, result) = self.renderer.stop_and_join();
+        // the worker usually fails as well once the device is gone
+        if let Err(e) = result {
+            log::warn!("{}", e);
         }
+        scenes
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let _delta_time = self.update_timing();
+    pub fn render(&mut self) -> Result<()> {
+        let delta_time = self.update_timing();
         let render_start_time = std::time::Instant::now();
+        let _span = self.trace.span("frame").arg("window", &self.title);
 
         // grab the current texture from the surface
-        let output = self.surface.get_current_texture()?;
+        let output = {
+            let _span = self.trace.span("acquire");
+            self.surface.get_current_texture().map_err(Error::Surface)?
+        };
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
@@ -184,32 +649,27 @@ impl<'win> State<'win> {
                 label: Some("render command encoder"),
             });
 
-        // keep track of storage buffers to be recycled after rendering
-        let mut storage_buffers = Vec::<construct::StorageBuffer>::new();
+        // render into the multisampled texture and resolve to the surface if MSAA is enabled
+        let target = match &self.msaa_view {
+            Some(msaa_view) => render::RenderTarget {
+                view: msaa_view,
+                resolve_target: Some(&view),
+            },
+            None => render::RenderTarget {
+                view: &view,
+                resolve_target: None,
+            },
+        };
 
-        {
-            // initialize render pass
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            // queue rendering for all elements
-            storage_buffers.extend(self.renderer.render(&mut render_pass));
-        }
+        // queue rendering for all elements and keep track of storage buffers to be recycled
+        let show_hud = self.hud.load(atomic::Ordering::SeqCst);
+        let storage_buffers = self.renderer.render(&mut encoder, &target, show_hud)?;
 
         // submit the render encoder
+        let submit_span = self.trace.span("submit");
         self.queue_arc.submit(std::iter::once(encoder.finish()));
+        self.renderer.frame_submitted();
+        drop(submit_span);
 
         // recycle storage buffers
         self.queue_arc.on_submitted_work_done(move || {
@@ -221,10 +681,24 @@ impl<'win> State<'win> {
         });
 
         // present the frame
-        output.present();
+        {
+            let _span = self.trace.span("present");
+            output.present();
+        }
+        self.renderer.frame_presented();
 
         // update stats
-        self.stats.update(render_start_time.elapsed().as_secs_f64());
+        let render_time = render_start_time.elapsed().as_secs_f64();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.render.update(render_time);
+            if delta_time > 0.0 {
+                stats
+                    .frame
+                    .update_interval(delta_time, self.refresh_interval);
+            }
+        }
+        self.renderer.record_frame(delta_time, render_time);
 
         Ok(())
     }
@@ -237,7 +711,7 @@ impl<'win> State<'win> {
             .as_secs_f64();
         if self.last_render_time.is_some() && delta_time > 0.0 {
             self.window
-                .set_title(&format!("FPS: {:.2}", 1.0 / delta_time));
+                .set_title(&format!("{} | FPS: {:.2}", self.title, 1.0 / delta_time));
         }
         self.last_render_time = Some(now);
         delta_time
@@ -252,6 +726,9 @@ impl<'win> State<'win> {
                 self.config.width = size.width;
                 self.config.height = size.height;
                 self.surface.configure(&self.device_arc, &self.config);
+                self.msaa_view =
+                    create_msaa_view(&self.device_arc, &self.config, self.sample_count);
+                self.renderer.resize(size.width, size.height);
             }
             None => {
                 // Reconfigure the surface with the current size
@@ -260,3 +737,51 @@ impl<'win> State<'win> {
         }
     }
 }
+
+/// Find the highest sample count the device accepts that does not exceed the requested one.
+fn supported_sample_count(gpu: &Gpu, format: wgpu::TextureFormat, requested: u32) -> u32 {
+    // without adapter specific format features the device only allows what every adapter supports
+    let features = gpu.device_arc.features();
+    let flags = match features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+        true => gpu.adapter.get_texture_format_features(format).flags,
+        false => format.guaranteed_format_features(features).flags,
+    };
+    let sample_count = [8, 4, 2, 1]
+        .into_iter()
+        .filter(|&count| count <= requested)
+        .find(|&count| flags.sample_count_supported(count))
+        .unwrap_or(1);
+    if sample_count != requested {
+        log::warn!(
+            "{}x MSAA is not supported, using {}x",
+            requested,
+            sample_count
+        );
+    }
+    sample_count
+}
+
+fn create_msaa_view(
+    device_arc: &Arc<wgpu::Device>,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+    let texture = device_arc.create_texture(&wgpu::TextureDescriptor {
+        label: Some("msaa texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}